@@ -0,0 +1,125 @@
+//! A write buffer that batches puts into fewer transactions, usable in front of any `Backend`.
+
+use crate::error::SumkinResult;
+use crate::traits::Backend;
+use crate::Revision;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tracing::warn;
+
+/// Collects puts in memory and writes them to the inner backend with a single `put_batch`,
+/// once `max_entries` have piled up, every `interval`, on `flush`, and when dropped.
+///
+/// This trades durability for throughput: a put that returns `Ok` has only been buffered. Until
+/// the next flush commits it, it isn't visible to reads or watches, and a crash loses it, so
+/// up to `interval` or `max_entries` worth of writes can go missing. Consecutive puts to the
+/// same key are written once, with the last value. A flush that fails keeps its entries
+/// buffered ahead of newer ones, to be retried by the next one; `flush` returns the error,
+/// background flushes log it.
+pub struct BufferedBackend<B: Backend + Send + Sync + 'static> {
+    shared: Arc<Shared<B>>,
+    max_entries: usize
+}
+
+struct Shared<B> {
+    inner: B,
+    entries: Mutex<Vec<(String, Vec<u8>)>>,
+    // Held for a whole flush, so batches reach the inner backend in the order they were taken.
+    flushing: tokio::sync::Mutex<()>
+}
+
+impl<B: Backend + Send + Sync + 'static> BufferedBackend<B> {
+    /// Buffers puts in front of `inner`, flushing every `interval` from a background task.
+    /// Must be called within a Tokio runtime.
+    pub fn new(inner: B, max_entries: usize, interval: Duration) -> Self {
+        let shared = Arc::new(Shared {
+            inner,
+            entries: Mutex::new(Vec::new()),
+            flushing: tokio::sync::Mutex::new(())
+        });
+        let weak = Arc::downgrade(&shared);
+        tokio::spawn(flush_periodically(weak, interval));
+        Self { shared, max_entries }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.shared.inner
+    }
+
+    /// Number of puts waiting for the next flush.
+    pub fn pending(&self) -> usize {
+        self.shared.entries.lock().unwrap().len()
+    }
+
+    /// Buffers a put of `value` to `name`, and flushes if that fills the buffer.
+    pub async fn put(&self, name: &str, value: &[u8]) -> SumkinResult<()> {
+        let full = {
+            let mut entries = self.shared.entries.lock().unwrap();
+            entries.push((name.to_owned(), value.to_vec()));
+            entries.len() >= self.max_entries
+        };
+        if full {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes every buffered put in one transaction, returning their revisions in the order
+    /// they were put. Puts coalesced into one write share its revision.
+    pub async fn flush(&self) -> SumkinResult<Vec<Revision>> {
+        self.shared.flush().await
+    }
+}
+
+impl<B: Backend + Send + Sync> Shared<B> {
+    async fn flush(&self) -> SumkinResult<Vec<Revision>> {
+        let _flushing = self.flushing.lock().await;
+        let taken = std::mem::take(&mut *self.entries.lock().unwrap());
+        if taken.is_empty() {
+            return Ok(Vec::new());
+        }
+        let batch: Vec<(&str, &[u8])> = taken.iter().map(|(name, value)| (name.as_str(), value.as_slice())).collect();
+        match self.inner.put_batch(&batch, true).await {
+            Ok(revisions) => Ok(revisions),
+            Err(e) => {
+                let mut entries = self.entries.lock().unwrap();
+                let newer = std::mem::replace(&mut *entries, taken);
+                entries.extend(newer);
+                Err(e)
+            }
+        }
+    }
+}
+
+async fn flush_periodically<B: Backend + Send + Sync>(shared: Weak<Shared<B>>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return
+        };
+        if let Err(e) = shared.flush().await {
+            warn!("Failed to flush buffered writes: {}", e);
+        }
+    }
+}
+
+impl<B: Backend + Send + Sync + 'static> Drop for BufferedBackend<B> {
+    fn drop(&mut self) {
+        if self.pending() == 0 {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let shared = self.shared.clone();
+            handle.spawn(async move {
+                if let Err(e) = shared.flush().await {
+                    warn!("Failed to flush buffered writes: {}", e);
+                }
+            });
+        } else {
+            warn!("Dropped a write buffer outside of a runtime, {} puts are lost", self.pending());
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! A read-through cache for `get`, usable in front of any `Backend`.
+
+use crate::error::SumkinResult;
+use crate::traits::{Backend, KeyStat, KeyValue, WriteStats};
+use crate::Revision;
+use async_trait::async_trait;
+use lru::LruCache;
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+/// Wraps a backend with a bounded LRU cache of current values, keyed by name.
+///
+/// A cached entry is only served while the store's current revision is still the one it was
+/// read at, so a hit costs a `current_revision` call instead of a lookup, and any write turns
+/// every entry into a miss. That check is what keeps writes that bypass the wrapper (through
+/// the inner backend or another handle on the same database) from being served stale; writes
+/// through the wrapper additionally drop the entries they touch. Only `get` of the current
+/// revision is cached. A `clear` that bypasses the wrapper restarts revisions, so it can make
+/// old entries look current again; clear through the wrapper.
+#[derive(Debug)]
+pub struct CachedBackend<B> {
+    inner: B,
+    entries: Mutex<LruCache<String, (Revision, KeyValue)>>
+}
+
+impl<B: Backend + Send + Sync> CachedBackend<B> {
+    /// Caches up to `capacity` keys in front of `inner`.
+    pub fn new(inner: B, capacity: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            entries: Mutex::new(LruCache::new(capacity))
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    fn invalidate(&self, name: &str) {
+        self.entries.lock().unwrap().pop(name);
+    }
+
+    fn invalidate_all(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl<B: Backend + Send + Sync> Backend for CachedBackend<B> {
+    async fn size(&self) -> SumkinResult<u64> {
+        self.inner.size().await
+    }
+
+    async fn current_revision(&self) -> SumkinResult<Revision> {
+        self.inner.current_revision().await
+    }
+
+    async fn compact_revision(&self) -> SumkinResult<Revision> {
+        self.inner.compact_revision().await
+    }
+
+    async fn count(&self, prefix: &str) -> SumkinResult<u64> {
+        self.inner.count(prefix).await
+    }
+
+    async fn log_count(&self) -> SumkinResult<u64> {
+        self.inner.log_count().await
+    }
+
+    async fn write_stats(&self) -> SumkinResult<WriteStats> {
+        self.inner.write_stats().await
+    }
+
+    async fn is_empty(&self) -> SumkinResult<bool> {
+        self.inner.is_empty().await
+    }
+
+    async fn prefix_exists(&self, prefix: &str) -> SumkinResult<bool> {
+        self.inner.prefix_exists(prefix).await
+    }
+
+    async fn count_by_top_level(&self) -> SumkinResult<Vec<(String, u64)>> {
+        self.inner.count_by_top_level().await
+    }
+
+    async fn count_all(&self, prefix: &str) -> SumkinResult<(u64, u64)> {
+        self.inner.count_all(prefix).await
+    }
+
+    async fn get_first(&self, names: &[&str]) -> SumkinResult<Option<(String, KeyValue)>> {
+        self.inner.get_first(names).await
+    }
+
+    async fn get_map(&self, names: &[&str]) -> SumkinResult<HashMap<String, KeyValue>> {
+        self.inner.get_map(names).await
+    }
+
+    async fn stat(&self, name: &str) -> SumkinResult<Option<KeyStat>> {
+        self.inner.stat(name).await
+    }
+
+    async fn put(&self, name: &str, value: &[u8]) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.put(name, value).await
+    }
+
+    async fn grant_lease(&self, ttl_seconds: i64) -> SumkinResult<i64> {
+        self.inner.grant_lease(ttl_seconds).await
+    }
+
+    async fn keep_alive(&self, lease_id: i64) -> SumkinResult<()> {
+        self.inner.keep_alive(lease_id).await
+    }
+
+    async fn active_leases(&self) -> SumkinResult<Vec<i64>> {
+        self.inner.active_leases().await
+    }
+
+    async fn attach_lease(&self, name: &str, lease_id: Option<i64>) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.attach_lease(name, lease_id).await
+    }
+
+    async fn touch(&self, name: &str) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.touch(name).await
+    }
+
+    async fn create_or_get(&self, name: &str, value: &[u8]) -> SumkinResult<(bool, KeyValue)> {
+        self.invalidate(name);
+        self.inner.create_or_get(name, value).await
+    }
+
+    async fn put_batch(&self, entries: &[(&str, &[u8])], coalesce: bool) -> SumkinResult<Vec<Revision>> {
+        for (name, _) in entries {
+            self.invalidate(name);
+        }
+        self.inner.put_batch(entries, coalesce).await
+    }
+
+    async fn bootstrap_if_empty(&self, prefix: &str, entries: &[(&str, &[u8])]) -> SumkinResult<bool> {
+        for (name, _) in entries {
+            self.invalidate(name);
+        }
+        self.inner.bootstrap_if_empty(prefix, entries).await
+    }
+
+    async fn put_batch_update(&self, entries: &[(&str, &[u8])]) -> SumkinResult<Vec<Revision>> {
+        for (name, _) in entries {
+            self.invalidate(name);
+        }
+        self.inner.put_batch_update(entries).await
+    }
+
+    async fn swap(&self, a: &str, b: &str) -> SumkinResult<(Revision, Revision)> {
+        self.invalidate(a);
+        self.invalidate(b);
+        self.inner.swap(a, b).await
+    }
+
+    async fn put_idempotent(&self, name: &str, value: &[u8], request_id: &str) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.put_idempotent(name, value, request_id).await
+    }
+
+    async fn append(&self, name: &str, data: &[u8]) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.append(name, data).await
+    }
+
+    async fn revert(&self, name: &str, to_revision: Revision) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.revert(name, to_revision).await
+    }
+
+    async fn put_tagged(&self, name: &str, tag: &str, value: &[u8]) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.put_tagged(name, tag, value).await
+    }
+
+    async fn put_with_meta(&self, name: &str, value: &[u8], content_type: &str) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.put_with_meta(name, value, content_type).await
+    }
+
+    async fn list_by_content_type(&self, content_type: &str) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_by_content_type(content_type).await
+    }
+
+    async fn set_annotation(&self, name: &str, label: &str, value: &str) -> SumkinResult<()> {
+        self.inner.set_annotation(name, label, value).await
+    }
+
+    async fn get_annotations(&self, name: &str) -> SumkinResult<HashMap<String, String>> {
+        self.inner.get_annotations(name).await
+    }
+
+    async fn list_annotated(&self, label: &str, value: &str) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_annotated(label, value).await
+    }
+
+    async fn revision_map(&self, prefix: &str) -> SumkinResult<BTreeMap<String, Revision>> {
+        self.inner.revision_map(prefix).await
+    }
+
+    async fn list_hashes(&self, prefix: &str) -> SumkinResult<Vec<(String, Revision, u32)>> {
+        self.inner.list_hashes(prefix).await
+    }
+
+    async fn value_size_histogram(&self, prefix: &str, buckets: &[i64]) -> SumkinResult<Vec<u64>> {
+        self.inner.value_size_histogram(prefix, buckets).await
+    }
+
+    async fn list_empty_values(&self, prefix: &str) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_empty_values(prefix).await
+    }
+
+    async fn list_by_tag(&self, tag: &str) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_by_tag(tag).await
+    }
+
+    async fn modified_between(&self, prefix: &str, start_ms: i64, end_ms: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.modified_between(prefix, start_ms, end_ms).await
+    }
+
+    async fn created_since(&self, prefix: &str, revision: Revision) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.created_since(prefix, revision).await
+    }
+
+    async fn list_stale(&self, prefix: &str, older_than_ms: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_stale(prefix, older_than_ms).await
+    }
+
+    async fn get(&self, name: &str, revision: Option<Revision>) -> SumkinResult<Option<KeyValue>> {
+        if revision.is_some() {
+            return self.inner.get(name, revision).await;
+        }
+        let current = self.inner.current_revision().await?;
+        if let Some((cached_at, kv)) = self.entries.lock().unwrap().get(name) {
+            if *cached_at == current {
+                return Ok(Some(kv.clone()));
+            }
+        }
+        let kv = self.inner.get(name, None).await?;
+        match &kv {
+            Some(kv) => {
+                self.entries.lock().unwrap().put(name.to_owned(), (current, kv.clone()));
+            }
+            None => self.invalidate(name)
+        }
+        Ok(kv)
+    }
+
+    async fn get_range_bytes(&self, name: &str, offset: i64, length: i64) -> SumkinResult<Option<Vec<u8>>> {
+        self.inner.get_range_bytes(name, offset, length).await
+    }
+
+    async fn list_current(&self, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_current(prefix, limit, include_deleted).await
+    }
+
+    async fn list_numeric_sorted(&self, prefix: &str, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_numeric_sorted(prefix, limit).await
+    }
+
+    async fn list_shard(&self, prefix: &str, shard: u32, total_shards: u32) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_shard(prefix, shard, total_shards).await
+    }
+
+    async fn list_prefixes(&self, prefixes: &[&str], limit_per: i64) -> SumkinResult<HashMap<String, Vec<KeyValue>>> {
+        self.inner.list_prefixes(prefixes, limit_per).await
+    }
+
+    async fn list_matching(&self, pattern: &str, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.list_matching(pattern, limit).await
+    }
+
+    async fn range_keys(&self, key: &[u8], range_end: &[u8], limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.range_keys(key, range_end, limit).await
+    }
+
+    async fn floor(&self, key: &str) -> SumkinResult<Option<KeyValue>> {
+        self.inner.floor(key).await
+    }
+
+    async fn ceiling(&self, key: &str) -> SumkinResult<Option<KeyValue>> {
+        self.inner.ceiling(key).await
+    }
+
+    async fn count_range(&self, key: &[u8], range_end: &[u8]) -> SumkinResult<u64> {
+        self.inner.count_range(key, range_end).await
+    }
+
+    async fn delete(&self, name: &str) -> SumkinResult<Revision> {
+        self.invalidate(name);
+        self.inner.delete(name).await
+    }
+
+    async fn delete_checked(&self, name: &str) -> SumkinResult<(bool, Revision)> {
+        self.invalidate(name);
+        self.inner.delete_checked(name).await
+    }
+
+    async fn delete_range_prev(&self, prefix: &str) -> SumkinResult<Vec<KeyValue>> {
+        self.invalidate_all();
+        self.inner.delete_range_prev(prefix).await
+    }
+
+    async fn changes_since(&self, prefix: &str, revision: Revision) -> SumkinResult<HashMap<String, KeyValue>> {
+        self.inner.changes_since(prefix, revision).await
+    }
+
+    async fn recent(&self, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.recent(limit).await
+    }
+
+    async fn recent_under(&self, prefix: &str, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.inner.recent_under(prefix, limit).await
+    }
+
+    async fn event_at(&self, revision: Revision) -> SumkinResult<Option<KeyValue>> {
+        self.inner.event_at(revision).await
+    }
+
+    async fn clear(&self) -> SumkinResult<()> {
+        self.invalidate_all();
+        self.inner.clear().await
+    }
+
+    async fn generation(&self) -> SumkinResult<u64> {
+        self.inner.generation().await
+    }
+
+    async fn prune_log(&self, up_to_revision: Revision) -> SumkinResult<u64> {
+        self.inner.prune_log(up_to_revision).await
+    }
+
+    async fn compact_prefix(&self, prefix: &str, up_to_revision: Revision) -> SumkinResult<u64> {
+        self.inner.compact_prefix(prefix, up_to_revision).await
+    }
+}
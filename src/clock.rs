@@ -0,0 +1,47 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of wall-clock time for everything the backend timestamps.
+pub trait Clock: Debug + Send + Sync {
+    /// Milliseconds since the Unix epoch.
+    fn now_millis(&self) -> i64;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+    }
+}
+
+/// Manually driven clock for tests. Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    now: Arc<AtomicI64>
+}
+
+impl MockClock {
+    pub fn new(now_millis: i64) -> Self {
+        Self {
+            now: Arc::new(AtomicI64::new(now_millis))
+        }
+    }
+
+    pub fn set(&self, now_millis: i64) {
+        self.now.store(now_millis, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, millis: i64) {
+        self.now.fetch_add(millis, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_millis(&self) -> i64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
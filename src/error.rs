@@ -1,4 +1,6 @@
 use snafu::{Snafu};
+use crate::Revision;
+use std::time::Duration;
 
 pub type SumkinResult<T> = Result<T, Error>;
 
@@ -9,11 +11,103 @@ pub enum Error {
 
     #[snafu(display("I/O error: {}", source))]
     IoError { source: std::io::Error },
+
+    #[snafu(display("Key not found: {}", name))]
+    KeyNotFound { name: String },
+
+    #[snafu(display("Lease not found: {}", id))]
+    LeaseNotFound { id: i64 },
+
+    #[snafu(display("Revision {} has been compacted (compacted up to {})", revision, compact_revision))]
+    Compacted { revision: Revision, compact_revision: Revision },
+
+    #[snafu(display("Invalid record on line {}: {}", line, reason))]
+    InvalidRecord { line: u64, reason: String },
+
+    #[snafu(display("Database schema doesn't match the configuration: {}", reason))]
+    SchemaMismatch { reason: String },
+
+    #[snafu(display("Revision {} would exceed the configured maximum of {}", revision, max_revision))]
+    RevisionLimit { revision: Revision, max_revision: Revision },
+
+    #[snafu(display("Timed out after {:?}", after))]
+    Timeout { after: Duration },
+
+    #[snafu(display("Checkpoint couldn't complete, the database is busy"))]
+    CheckpointBusy,
+
+    #[snafu(display("The backend has been closed"))]
+    Closed,
+
+    #[snafu(display("All write slots are held by {} open transactions", held))]
+    WritesHeld { held: usize },
+
+    #[snafu(display("Invalid argument: {}", reason))]
+    InvalidArgument { reason: String },
+
+    #[snafu(display("Key is not valid UTF-8: {}", source))]
+    InvalidKey { source: std::str::Utf8Error },
+}
+
+/// A stable classification of `Error`, for mapping onto HTTP or gRPC status codes without
+/// matching on variants or database error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    NotFound,
+    /// A constraint was violated, e.g. a write raced another one.
+    Conflict,
+    /// The database is busy, locked, closed or can't be reached; retrying may help.
+    Unavailable,
+    /// The input was rejected.
+    Invalid,
+    Internal,
+    ReadOnly,
+    Timeout,
+    Compacted,
+}
+
+impl Error {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Error::BackendError { source } => sqlx_code(source),
+            Error::IoError { .. } | Error::Closed | Error::CheckpointBusy | Error::WritesHeld { .. } => ErrorCode::Unavailable,
+            Error::KeyNotFound { .. } | Error::LeaseNotFound { .. } => ErrorCode::NotFound,
+            Error::Compacted { .. } => ErrorCode::Compacted,
+            Error::InvalidRecord { .. } | Error::SchemaMismatch { .. } | Error::InvalidKey { .. } | Error::InvalidArgument { .. } => ErrorCode::Invalid,
+            Error::Timeout { .. } => ErrorCode::Timeout,
+            Error::RevisionLimit { .. } => ErrorCode::Internal,
+        }
+    }
+}
+
+/// Classifies an sqlx error, by primary SQLite result code for database errors.
+fn sqlx_code(source: &sqlx::Error) -> ErrorCode {
+    match source {
+        sqlx::Error::RowNotFound => ErrorCode::NotFound,
+        sqlx::Error::PoolTimedOut => ErrorCode::Timeout,
+        sqlx::Error::PoolClosed | sqlx::Error::Io(_) => ErrorCode::Unavailable,
+        sqlx::Error::Database(e) => {
+            let code = e.code().and_then(|code| code.parse::<i32>().ok()).unwrap_or_default();
+            match code & 0xff {
+                // SQLITE_BUSY, SQLITE_LOCKED, SQLITE_IOERR, SQLITE_FULL, SQLITE_CANTOPEN
+                5 | 6 | 10 | 13 | 14 => ErrorCode::Unavailable,
+                // SQLITE_READONLY
+                8 => ErrorCode::ReadOnly,
+                // SQLITE_CONSTRAINT
+                19 => ErrorCode::Conflict,
+                _ => ErrorCode::Internal,
+            }
+        }
+        _ => ErrorCode::Internal,
+    }
 }
 
 impl From<sqlx::Error> for Error {
     fn from(source: sqlx::Error) -> Error {
-        Error::BackendError { source }
+        match source {
+            sqlx::Error::PoolClosed => Error::Closed,
+            source => Error::BackendError { source }
+        }
     }
 }
 
@@ -22,3 +116,9 @@ impl From<std::io::Error> for Error {
         Error::IoError { source }
     }
 }
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(source: std::str::Utf8Error) -> Error {
+        Error::InvalidKey { source }
+    }
+}
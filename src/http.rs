@@ -0,0 +1,78 @@
+//! Minimal REST adapter over any `Backend`.
+//!
+//! | Method   | Path             | Backend call                           | Response                        |
+//! |----------|------------------|----------------------------------------|---------------------------------|
+//! | `GET`    | `/kv/<key>`      | `get("/<key>", None)`                  | value bytes, or 404             |
+//! | `PUT`    | `/kv/<key>`      | `put("/<key>", body)`                  | revision                        |
+//! | `DELETE` | `/kv/<key>`      | `delete("/<key>")`                     | revision                        |
+//! | `GET`    | `/list/<prefix>` | `list_current("/<prefix>", -1, false)` | matching keys, one per line     |
+//!
+//! Keys are taken verbatim from the path, so `/list/app/` lists everything below `/app/`.
+//! Every response carries the revision it reflects in the `X-Revision` header.
+
+use crate::error::Error;
+use crate::traits::Backend;
+use crate::Revision;
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use std::sync::Arc;
+
+pub type SharedBackend = Arc<dyn Backend + Send + Sync>;
+
+const REVISION_HEADER: &str = "x-revision";
+
+/// Builds the routes described in the module docs. Serve it with `axum::serve`.
+pub fn router(backend: SharedBackend) -> Router {
+    Router::new()
+        .route("/kv/*key", get(get_key).put(put_key).delete(delete_key))
+        .route("/list/*prefix", get(list_prefix))
+        .with_state(backend)
+}
+
+fn with_revision(revision: Revision, response: impl IntoResponse) -> Response {
+    ([(REVISION_HEADER, revision.to_string())], response).into_response()
+}
+
+fn error_response(err: Error) -> Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+}
+
+async fn get_key(State(backend): State<SharedBackend>, Path(key): Path<String>) -> Response {
+    match backend.get(&format!("/{}", key), None).await {
+        Ok(Some(kv)) => with_revision(*kv.mod_revision(), kv.value().clone().unwrap_or_default()),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn put_key(State(backend): State<SharedBackend>, Path(key): Path<String>, body: Bytes) -> Response {
+    match backend.put(&format!("/{}", key), &body).await {
+        Ok(revision) => with_revision(revision, revision.to_string()),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn delete_key(State(backend): State<SharedBackend>, Path(key): Path<String>) -> Response {
+    match backend.delete(&format!("/{}", key)).await {
+        Ok(revision) => with_revision(revision, revision.to_string()),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn list_prefix(State(backend): State<SharedBackend>, Path(prefix): Path<String>) -> Response {
+    let revision = match backend.current_revision().await {
+        Ok(revision) => revision,
+        Err(e) => return error_response(e),
+    };
+    match backend.list_current(&format!("/{}", prefix), -1, false).await {
+        Ok(kvs) => {
+            let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+            with_revision(revision, keys.join("\n"))
+        }
+        Err(e) => error_response(e),
+    }
+}
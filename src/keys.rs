@@ -0,0 +1,131 @@
+/// How a prefix argument selects keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixMatch {
+    /// Only the key itself.
+    Exact(String),
+    /// Every key starting with the prefix.
+    Prefix(String),
+}
+
+impl PrefixMatch {
+    /// The `LIKE ... ESCAPE '\'` pattern for this match, with wildcards in the key escaped.
+    pub fn like_pattern(&self) -> String {
+        match self {
+            PrefixMatch::Exact(key) => escape_like(key),
+            PrefixMatch::Prefix(prefix) => format!("{}%", escape_like(prefix)),
+        }
+    }
+}
+
+/// What a prefix argument not ending in `/` means to list, count, watch and the other
+/// prefix-taking calls. A prefix ending in `/` selects everything below it in both modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrefixMode {
+    /// `/root` names exactly the key `/root`, and the empty string names no key.
+    #[default]
+    Exact,
+    /// `/root` selects the subtree below it, same as `/root/`, and the empty string selects
+    /// every key. The key `/root` itself is not included; read it with `get`.
+    Subtree,
+}
+
+/// Single place deciding what a prefix argument means: one ending in `/` selects everything
+/// below it, anything else (including the empty string) names exactly one key.
+pub fn normalize_prefix(prefix: &str) -> PrefixMatch {
+    normalize_prefix_with(prefix, PrefixMode::Exact)
+}
+
+/// Same as `normalize_prefix`, with prefixes not ending in `/` read according to `mode`.
+pub fn normalize_prefix_with(prefix: &str, mode: PrefixMode) -> PrefixMatch {
+    if prefix.ends_with('/') {
+        return PrefixMatch::Prefix(prefix.to_owned());
+    }
+    match mode {
+        PrefixMode::Exact => PrefixMatch::Exact(prefix.to_owned()),
+        PrefixMode::Subtree if prefix.is_empty() => PrefixMatch::Prefix(String::new()),
+        PrefixMode::Subtree => PrefixMatch::Prefix(format!("{}/", prefix)),
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Returns the `range_end` that, paired with `prefix` as the range start, selects every key
+/// starting with `prefix` (etcd's `GetPrefixRangeEnd`). An empty or all-`0xff` prefix maps to
+/// `"\0"`, which means "no upper bound".
+pub fn prefix_range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    vec![0]
+}
+
+/// The shard of `total_shards` that `key` belongs to, from its 64-bit FNV-1a hash. The hash is
+/// fixed, so assignments never change between processes or versions. Panics if `total_shards`
+/// is 0.
+pub fn shard_of(key: &str, total_shards: u32) -> u32 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % u64::from(total_shards)) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_range_end_increments_last_byte() {
+        assert_eq!(b"/root0".to_vec(), prefix_range_end(b"/root/"));
+        assert_eq!(b"b".to_vec(), prefix_range_end(b"a\xff"));
+        assert_eq!(vec![0], prefix_range_end(b""));
+        assert_eq!(vec![0], prefix_range_end(b"\xff\xff"));
+    }
+
+    #[test]
+    fn normalize_prefix_variants() {
+        assert_eq!(PrefixMatch::Prefix("/".to_owned()), normalize_prefix("/"));
+        assert_eq!(PrefixMatch::Exact("/a".to_owned()), normalize_prefix("/a"));
+        assert_eq!(PrefixMatch::Prefix("/a/".to_owned()), normalize_prefix("/a/"));
+        assert_eq!(PrefixMatch::Exact("".to_owned()), normalize_prefix(""));
+
+        assert_eq!("/%", normalize_prefix("/").like_pattern());
+        assert_eq!("/a", normalize_prefix("/a").like_pattern());
+        assert_eq!("/a/%", normalize_prefix("/a/").like_pattern());
+        assert_eq!("", normalize_prefix("").like_pattern());
+        assert_eq!("/a\\_b/\\%", normalize_prefix("/a_b/%").like_pattern());
+        assert_eq!("/a\\_b/\\%/%", normalize_prefix("/a_b/%/").like_pattern());
+    }
+
+    #[test]
+    fn normalize_prefix_subtree() {
+        assert_eq!(PrefixMatch::Prefix("/a/".to_owned()), normalize_prefix_with("/a", PrefixMode::Subtree));
+        assert_eq!(PrefixMatch::Prefix("/a/".to_owned()), normalize_prefix_with("/a/", PrefixMode::Subtree));
+        assert_eq!(PrefixMatch::Prefix("".to_owned()), normalize_prefix_with("", PrefixMode::Subtree));
+        assert_eq!(normalize_prefix("/a"), normalize_prefix_with("/a", PrefixMode::Exact));
+
+        assert_eq!("%", normalize_prefix_with("", PrefixMode::Subtree).like_pattern());
+        assert_eq!("/a\\_b/%", normalize_prefix_with("/a_b", PrefixMode::Subtree).like_pattern());
+    }
+
+    #[test]
+    fn shard_of_is_stable() {
+        assert_eq!((0xcbf2_9ce4_8422_2325_u64 % 7) as u32, shard_of("", 7));
+        assert_eq!((0xaf63_dc4c_8601_ec8c_u64 % 7) as u32, shard_of("a", 7));
+        assert_eq!(0, shard_of("/any/key", 1));
+    }
+}
@@ -5,8 +5,18 @@ pub mod traits;
 pub mod error;
 pub mod sqlite;
 pub mod log;
+pub mod clock;
+pub mod keys;
+pub mod watch;
+pub mod cache;
+pub mod buffer;
+#[cfg(feature = "http")]
+pub mod http;
 
-pub type Revision = i64;
+mod ndjson;
+mod revision;
+
+pub use revision::Revision;
 
 #[cfg(test)]
 mod tests {
@@ -1,7 +1,6 @@
+/*
 use crate::traits::Backend;
 
-
-/*
 pub struct EventLog<T: Backend> {
     backed: T
 }
@@ -0,0 +1,69 @@
+use crate::traits::KeyValue;
+use crate::watch::WatchEvent;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// One line of an NDJSON export: a key's current revision, with the value base64-encoded.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Record {
+    pub key: String,
+    #[serde(default)]
+    pub value: Option<String>,
+    #[serde(default)]
+    pub create_revision: Option<i64>,
+    #[serde(default)]
+    pub mod_revision: Option<i64>,
+    #[serde(default)]
+    pub lease: Option<i64>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub mtime: Option<i64>,
+}
+
+impl Record {
+    /// The decoded value; a missing value reads as empty.
+    pub fn decode_value(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        match &self.value {
+            Some(value) => STANDARD.decode(value),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl From<&KeyValue> for Record {
+    fn from(kv: &KeyValue) -> Self {
+        Record {
+            key: kv.key().clone(),
+            value: kv.value().as_ref().map(|value| STANDARD.encode(value)),
+            create_revision: Some((*kv.create_revision()).into()),
+            mod_revision: Some((*kv.mod_revision()).into()),
+            lease: *kv.lease(),
+            tag: kv.tag().clone(),
+            content_type: kv.content_type().clone(),
+            mtime: *kv.mtime(),
+        }
+    }
+}
+
+/// The JSON form of a `WatchEvent`, tagged by `type`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Event {
+    Put(Record),
+    Delete(Record),
+    Compacted { compact_revision: i64 },
+}
+
+impl From<&WatchEvent> for Event {
+    fn from(event: &WatchEvent) -> Self {
+        match event {
+            WatchEvent::Put { kv, .. } => Event::Put(kv.into()),
+            WatchEvent::Delete { kv, .. } => Event::Delete(kv.into()),
+            WatchEvent::Compacted { compact_revision } => Event::Compacted { compact_revision: (*compact_revision).into() },
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use sqlx::decode::Decode;
+use sqlx::encode::{Encode, IsNull};
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::{Sqlite, SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef};
+use sqlx::Type;
+use std::fmt;
+
+/// A position in the log. Every write gets the next revision; revisions never repeat.
+///
+/// Kept distinct from plain `i64` so lease ids, counts and the like can't be passed where a
+/// revision is expected. Stored as an SQLite integer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Revision(i64);
+
+impl Revision {
+    /// The revision immediately after this one.
+    pub fn next(self) -> Revision {
+        Revision(self.0 + 1)
+    }
+
+    /// The revision immediately before this one.
+    pub fn prev(self) -> Revision {
+        Revision(self.0 - 1)
+    }
+}
+
+impl From<i64> for Revision {
+    fn from(revision: i64) -> Self {
+        Revision(revision)
+    }
+}
+
+impl From<Revision> for i64 {
+    fn from(revision: Revision) -> Self {
+        revision.0
+    }
+}
+
+impl PartialEq<i64> for Revision {
+    fn eq(&self, other: &i64) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<Revision> for i64 {
+    fn eq(&self, other: &Revision) -> bool {
+        *self == other.0
+    }
+}
+
+impl Type<Sqlite> for Revision {
+    fn type_info() -> SqliteTypeInfo {
+        <i64 as Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &SqliteTypeInfo) -> bool {
+        <i64 as Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> Encode<'q, Sqlite> for Revision {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> IsNull {
+        <i64 as Encode<'q, Sqlite>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+impl<'r> Decode<'r, Sqlite> for Revision {
+    fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+        <i64 as Decode<'r, Sqlite>>::decode(value).map(Revision)
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_and_conversions() {
+        let revision = Revision::from(41);
+        assert_eq!(Revision::from(42), revision.next());
+        assert_eq!(Revision::from(40), revision.prev());
+        assert_eq!(41i64, i64::from(revision));
+        assert_eq!("41", revision.to_string());
+        assert!(revision < revision.next());
+    }
+
+    #[tokio::test]
+    async fn binds_and_decodes() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let revision: Revision = sqlx::query_scalar("SELECT ? + 1").bind(Revision::from(7)).fetch_one(&pool).await.unwrap();
+        assert_eq!(Revision::from(8), revision);
+        let missing: Option<Revision> = sqlx::query_scalar("SELECT NULL").fetch_one(&pool).await.unwrap();
+        assert_eq!(None, missing);
+    }
+}
@@ -1,46 +1,483 @@
-use crate::error::SumkinResult;
+use crate::error::{Error, SumkinResult};
 use sqlx::{sqlite::{SqlitePoolOptions,SqliteConnectOptions, SqliteJournalMode}, SqlitePool, Executor};
-use tracing::{info, debug};
+use tracing::{info, debug, warn};
 use std::path::Path;
+use std::str::FromStr;
 use std::fs::OpenOptions;
-use crate::traits::{Backend,KeyValue};
-use sqlx::{Row, Transaction, Sqlite};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::traits::{Backend, KeyStat, KeyValue, WriteStats};
+use sqlx::{Acquire, Row, Transaction, Sqlite};
 use async_trait::async_trait;
 use crate::Revision;
+use crate::clock::{Clock, SystemClock};
+use crate::watch::{ChangeEvent, ChangeOp, WatchEvent};
+use crate::keys::{normalize_prefix, normalize_prefix_with, shard_of, PrefixMatch, PrefixMode};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use crate::ndjson::{Event, Record};
+use bytes::Bytes;
+use crc::{Crc, CRC_32_ISO_HDLC};
+use sha2::{Digest, Sha256};
+use futures_util::TryStreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
 
 mod sql {
-    pub static COLUMNS: &str = "kv.id AS theid, kv.name, kv.created, kv.deleted, kv.create_revision, kv.prev_revision, kv.lease, kv.value, kv.old_value";
+    pub static COLUMNS: &str = "kv.id AS theid, kv.name, kv.created, kv.deleted, kv.create_revision, kv.prev_revision, kv.lease, COALESCE(kv.value, (SELECT b.value FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref)) AS value, kv.old_value, kv.tag, kv.mtime, kv.content_type";
     pub static SIZE_SQL: &str = "SELECT SUM(pgsize) FROM dbstat";
+    pub static SIZE_BREAKDOWN_SQL: &str = "SELECT name, SUM(pgsize) AS size FROM dbstat GROUP BY name ORDER BY name";
     pub static CURRENT_REVISION_SQL: &str = "SELECT MAX(rkv.id) AS id FROM sumkin AS rkv";
     pub static COMPACT_REV_SQL: &str = "SELECT MAX(crkv.prev_revision) AS prev_revision
 		FROM sumkin AS crkv
 		WHERE crkv.name = 'compact_rev_key'";
-    pub static INSERT: &str = "INSERT INTO sumkin(name, created, deleted, create_revision, prev_revision, lease, value, old_value) values(?, ?, ?, ?, ?, ?, ?, ?)";
+    // A NULL id lets AUTOINCREMENT pick the revision; `revision_seq` mode passes one explicitly.
+    pub static INSERT: &str = "INSERT INTO sumkin(id, name, created, deleted, create_revision, prev_revision, lease, value, old_value, tag, mtime, content_type, value_ref, old_value_ref) values(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
+    // Never hands out a revision at or below one already in the log, so the mode can be turned
+    // on for an existing database.
+    pub static NEXT_REVISION_SQL: &str = "UPDATE revision_seq SET value = MAX(value, (SELECT COALESCE(MAX(id), 0) FROM sumkin)) + 1";
+    pub static REVISION_SEQ_SQL: &str = "SELECT value FROM revision_seq";
+    pub static INSERT_BLOB_SQL: &str = "INSERT OR IGNORE INTO sumkin_blobs(hash, value) VALUES(?, ?)";
+    pub static PRUNE_ANNOTATIONS_SQL: &str = "DELETE FROM annotations WHERE revision NOT IN (SELECT id FROM sumkin)";
+    pub static SET_ANNOTATION_SQL: &str = "INSERT INTO annotations(name, revision, label, value) VALUES(?, ?, ?, ?)
+        ON CONFLICT(name, revision, label) DO UPDATE SET value = excluded.value";
+    pub static GET_ANNOTATIONS_SQL: &str = "SELECT a.label, a.value
+        FROM annotations AS a
+        WHERE
+              a.revision = (SELECT MAX(mkv.id) FROM sumkin AS mkv WHERE mkv.name = ?)";
+    pub static PRUNE_BLOBS_SQL: &str = "DELETE FROM sumkin_blobs
+        WHERE
+              hash NOT IN (SELECT value_ref FROM sumkin WHERE value_ref IS NOT NULL)
+              AND hash NOT IN (SELECT old_value_ref FROM sumkin WHERE old_value_ref IS NOT NULL)";
+    pub static USER_VERSION_SQL: &str = "PRAGMA user_version";
+    pub static COUNT_ALL_SQL: &str = "SELECT
+            COALESCE(SUM(kv.deleted = 0), 0) AS live,
+            COALESCE(SUM(kv.deleted != 0), 0) AS tombstoned
+        FROM sumkin AS kv
+        JOIN (
+            SELECT MAX(mkv.id) AS id
+            FROM sumkin AS mkv
+            WHERE
+                mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
+            GROUP BY mkv.name) maxkv
+        ON maxkv.id = kv.id";
+    pub static PRUNE_LOG_SQL: &str = "DELETE FROM sumkin
+        WHERE
+            id <= ?
+            AND name != 'compact_rev_key'
+            AND id NOT IN (
+                SELECT kv.id
+                FROM sumkin AS kv
+                JOIN (
+                    SELECT MAX(mkv.id) AS id
+                    FROM sumkin AS mkv
+                    GROUP BY mkv.name) maxkv
+                ON maxkv.id = kv.id
+                WHERE kv.deleted = 0)";
+    pub static UPDATE_COMPACT_REV_SQL: &str = "UPDATE sumkin SET prev_revision = ? WHERE name = 'compact_rev_key'";
+    pub static STAT_SQL: &str = "SELECT
+            kv.create_revision,
+            kv.id AS mod_revision,
+            (SELECT COUNT(vkv.id)
+                FROM sumkin AS vkv
+                WHERE
+                    vkv.name = kv.name
+                    AND vkv.deleted = 0
+                    AND vkv.id > COALESCE((
+                        SELECT MAX(dkv.id)
+                        FROM sumkin AS dkv
+                        WHERE dkv.name = kv.name AND dkv.deleted != 0), 0)) AS version,
+            COALESCE(LENGTH(kv.value), (SELECT LENGTH(b.value) FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref), 0) AS value_size,
+            kv.lease
+        FROM sumkin AS kv
+        WHERE
+            kv.id = (SELECT MAX(mkv.id) FROM sumkin AS mkv WHERE mkv.name = ?)
+            AND kv.deleted = 0";
+    pub static KINE_TABLE_EXISTS_SQL: &str = "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'kine'";
+    pub static LOG_IS_EMPTY_SQL: &str = "SELECT NOT EXISTS(SELECT 1 FROM sumkin LIMIT 1)";
+    pub static IMPORT_KINE_SQL: &str = "INSERT INTO sumkin(id, name, created, deleted, create_revision, prev_revision, lease, value, old_value)
+        SELECT id, name, created, deleted, create_revision, prev_revision, lease, value, old_value
+        FROM kine
+        ORDER BY id ASC";
+    pub static IS_EMPTY_SQL: &str = "SELECT NOT EXISTS(
+            SELECT 1
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE kv.deleted = 0 AND kv.name != 'compact_rev_key'
+            LIMIT 1)";
+    pub static PREFIX_EXISTS_SQL: &str = "SELECT EXISTS(
+            SELECT 1
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE kv.deleted = 0 AND kv.name != 'compact_rev_key'
+            LIMIT 1)";
+    pub static GRANT_LEASE_SQL: &str = "INSERT INTO leases(ttl, expires_at) VALUES(?, ?)";
+    pub static LEASE_EXISTS_SQL: &str = "SELECT EXISTS(SELECT 1 FROM leases WHERE id = ?)";
+    pub static KEEP_ALIVE_SQL: &str = "UPDATE leases SET expires_at = ? + ttl * 1000 WHERE id = ?";
+    // Creates the key only if it has no live revision, in a single statement so that
+    // concurrent callers serialize on the write lock instead of racing a read. The
+    // create_revision is filled in afterwards with SET_CREATE_REVISION_SQL.
+    pub static CREATE_IF_ABSENT_SQL: &str = "INSERT INTO sumkin(id, name, created, deleted, create_revision, prev_revision, lease, value, old_value, tag, mtime, content_type, value_ref)
+        SELECT ?, ?, 1, 0, 0, NULL, ?, ?, NULL, NULL, ?, NULL, ?
+        WHERE NOT EXISTS (
+            SELECT 1
+            FROM sumkin AS kv
+            WHERE
+                  kv.id = (SELECT MAX(mkv.id) FROM sumkin AS mkv WHERE mkv.name = ?) AND kv.deleted = 0)";
+    // A created row's create_revision is its own id, which is only known once inserted.
+    pub static SET_CREATE_REVISION_SQL: &str = "UPDATE sumkin SET create_revision = id WHERE id = ?";
+    // One row per violation: the create rows whose create_revision isn't their own id (kine
+    // writes 0 there, meaning the same), tombstones with no earlier row of their key, and
+    // prev_revisions that don't point backwards. Pruning drops the rows a tombstone follows,
+    // so tombstones are only checked on a log that was never compacted.
+    pub static CHECK_INVARIANTS_SQL: &str = "SELECT 'create_revision' AS kind, kv.name, kv.id, kv.create_revision AS other
+        FROM sumkin AS kv
+        WHERE
+              kv.created = 1 AND kv.name != 'compact_rev_key' AND kv.create_revision != kv.id AND kv.create_revision != 0
+        UNION ALL
+        SELECT 'orphan_tombstone' AS kind, kv.name, kv.id, NULL AS other
+        FROM sumkin AS kv
+        WHERE
+              kv.deleted = 1
+              AND NOT EXISTS (SELECT 1 FROM sumkin AS ckv WHERE ckv.name = 'compact_rev_key')
+              AND NOT EXISTS (SELECT 1 FROM sumkin AS pkv WHERE pkv.name = kv.name AND pkv.id < kv.id)
+        UNION ALL
+        SELECT 'prev_revision' AS kind, kv.name, kv.id, kv.prev_revision AS other
+        FROM sumkin AS kv
+        WHERE
+              kv.name != 'compact_rev_key' AND kv.prev_revision >= kv.id
+        ORDER BY 3";
+    // The create rows of each key since its last tombstone; a key created once has one.
+    pub static FIND_DUPLICATES_SQL: &str = "WITH live_creates AS (
+            SELECT kv.name, kv.id
+            FROM sumkin AS kv
+            WHERE
+                  kv.created = 1 AND kv.deleted = 0 AND kv.name != 'compact_rev_key'
+                  AND kv.id > COALESCE((SELECT MAX(dkv.id) FROM sumkin AS dkv WHERE dkv.name = kv.name AND dkv.deleted = 1), 0))
+        SELECT name, id
+        FROM live_creates
+        WHERE
+              name IN (SELECT name FROM live_creates GROUP BY name HAVING COUNT(*) > 1)
+        ORDER BY name ASC, id ASC";
+    pub static GET_REQUEST_SQL: &str = "SELECT revision FROM requests WHERE request_id = ?";
+    pub static INSERT_REQUEST_SQL: &str = "INSERT INTO requests(request_id, revision) VALUES(?, ?)";
+    // substr() on a BLOB counts bytes and is 1-indexed.
+    pub static GET_RANGE_BYTES_SQL: &str = "SELECT substr(COALESCE(kv.value, (SELECT b.value FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref)), ?, ?) AS value
+        FROM sumkin AS kv
+        WHERE
+              kv.id = (SELECT MAX(mkv.id) FROM sumkin AS mkv WHERE mkv.name = ?) AND kv.deleted = 0";
+    pub static HARD_DELETE_SQL: &str = "DELETE FROM sumkin WHERE name = ?";
+    // Hands a hard delete the revision AUTOINCREMENT would have given its row, so the next
+    // write still gets a higher one.
+    pub static SKIP_REVISION_SQL: &str = "UPDATE sqlite_sequence SET seq = MAX(seq, (SELECT COALESCE(MAX(id), 0) FROM sumkin)) + 1 WHERE name = 'sumkin'";
+    pub static SEQUENCE_SQL: &str = "SELECT seq FROM sqlite_sequence WHERE name = 'sumkin'";
+    pub static CLEAR_SQL: &str = "DELETE FROM sumkin;
+        DELETE FROM requests;
+        DELETE FROM sqlite_sequence WHERE name = 'sumkin';";
+    pub static GENERATION_SQL: &str = "SELECT COALESCE((SELECT value FROM sumkin_meta WHERE name = 'generation'), 0)";
+    pub static BUMP_GENERATION_SQL: &str = "INSERT INTO sumkin_meta(name, value) VALUES('generation', 1)
+        ON CONFLICT(name) DO UPDATE SET value = value + 1";
+    // kine writes lease 0 for keys without one.
+    pub static ACTIVE_LEASES_SQL: &str = "SELECT DISTINCT kv.lease
+        FROM sumkin AS kv
+        JOIN (
+            SELECT MAX(mkv.id) AS id
+            FROM sumkin AS mkv
+            GROUP BY mkv.name) maxkv
+        ON maxkv.id = kv.id
+        WHERE
+              kv.deleted = 0 AND kv.lease IS NOT NULL AND kv.lease != 0
+        ORDER BY kv.lease ASC";
+    pub static CHECKPOINT_SQL: &str = "PRAGMA wal_checkpoint(FULL)";
+    pub static FREELIST_COUNT_SQL: &str = "PRAGMA freelist_count";
+    pub static PAGE_COUNT_SQL: &str = "PRAGMA page_count";
+    pub static WRITE_STATS_SQL: &str = "SELECT
+            COUNT(kv.id) AS rows_written,
+            COALESCE(SUM(kv.created != 0), 0) AS creates,
+            COALESCE(SUM(kv.deleted != 0), 0) AS tombstones,
+            COALESCE(SUM(COALESCE(LENGTH(kv.value), (SELECT LENGTH(b.value) FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref))), 0) AS bytes_written
+        FROM sumkin AS kv
+        WHERE kv.name != 'compact_rev_key'";
+    pub static LIST_HASHES_SQL: &str = "SELECT kv.name, kv.id, COALESCE(kv.value, (SELECT b.value FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref)) AS value
+        FROM sumkin AS kv
+        JOIN (
+            SELECT MAX(mkv.id) AS id
+            FROM sumkin AS mkv
+            WHERE
+                mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
+            GROUP BY mkv.name) maxkv
+        ON maxkv.id = kv.id
+        WHERE
+              kv.deleted = 0
+        ORDER BY kv.name ASC";
+    // `{}` takes one `WHEN size < ? THEN i` per bucket.
+    pub static VALUE_SIZE_HISTOGRAM_SQL: &str = "SELECT CASE {} ELSE ? END AS bucket, COUNT(*) AS count
+        FROM (
+            SELECT COALESCE(LENGTH(kv.value), (SELECT LENGTH(b.value) FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref), 0) AS size
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0)
+        GROUP BY bucket";
+    pub static WARM_UP_SQL: &str = "SELECT 1";
+    pub static LOG_COUNT_SQL: &str = "SELECT COUNT(*) FROM sumkin";
+    pub static IS_LOG_EMPTY_SQL: &str = "SELECT NOT EXISTS(SELECT 1 FROM sumkin)";
+    pub static LOG_TABLE_SQL: &str = "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = 'sumkin'";
+    pub static RETAIN_SQL: &str = "DELETE FROM sumkin
+        WHERE
+              name = ? AND id NOT IN (SELECT rkv.id FROM sumkin AS rkv WHERE rkv.name = ? ORDER BY rkv.id DESC LIMIT ?)";
+    pub static REVOKE_LEASE_SQL: &str = "DELETE FROM leases WHERE id = ?";
+    pub static EXPIRED_LEASES_SQL: &str = "SELECT id FROM leases WHERE expires_at <= ? ORDER BY id ASC";
+    pub static COUNT_BY_TOP_LEVEL_SQL: &str = "SELECT
+            CASE WHEN INSTR(rest, '/') > 0 THEN SUBSTR(rest, 1, INSTR(rest, '/') - 1) ELSE rest END AS segment,
+            COUNT(*) AS count
+        FROM (
+            SELECT CASE WHEN SUBSTR(kv.name, 1, 1) = '/' THEN SUBSTR(kv.name, 2) ELSE kv.name END AS rest
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE mkv.name != 'compact_rev_key'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0)
+        GROUP BY segment
+        ORDER BY segment";
+    pub static COUNT_RANGE_SQL: &str = "SELECT COUNT(kv.id) AS count
+        FROM sumkin AS kv
+        JOIN (
+            SELECT MAX(mkv.id) AS id
+            FROM sumkin AS mkv
+            WHERE
+                mkv.name >= ?
+                {}
+            GROUP BY mkv.name) maxkv
+        ON maxkv.id = kv.id
+        WHERE
+              kv.deleted = 0 AND kv.name != 'compact_rev_key'";
     lazy_static! {
-        static ref GET_REVISION_SQL: String = format!("SELECT
-			0, 0, %s
-			FROM sumkin AS kv
-			WHERE kv.id = ? {}", COLUMNS);
+        pub static ref COMPACT_PREFIX_SQL: String = PRUNE_LOG_SQL.replace("AND name != 'compact_rev_key'", "AND name != 'compact_rev_key' AND name LIKE ? ESCAPE '\\'");
+        pub static ref REVISION_MAP_SQL: String = LIST_HASHES_SQL.replace(", COALESCE(kv.value, (SELECT b.value FROM sumkin_blobs AS b WHERE b.hash = kv.value_ref)) AS value", "");
+        pub static ref GET_REVISION_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            WHERE kv.id = ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref GET_REVISIONS_SQL: String = GET_REVISION_SQL.replace("WHERE kv.id = ?", "WHERE kv.id IN ({})
+            ORDER BY kv.id ASC");
+        pub static ref GET_AS_OF_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            WHERE
+                  kv.name = ? AND kv.id <= ?
+            ORDER BY kv.id DESC
+            LIMIT 1", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
         pub static ref LIST_SQL: String = format!("SELECT ({}), ({}), {}
             FROM sumkin AS kv
             JOIN (
                 SELECT MAX(mkv.id) AS id
                 FROM sumkin AS mkv
                 WHERE
-                    mkv.name LIKE ?
+                    mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
                     {{}}
                 GROUP BY mkv.name) maxkv
             ON maxkv.id = kv.id
             WHERE
                   (kv.deleted = 0 OR ?)
             ORDER BY kv.id ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
-        pub static ref COUNT_SQL: String = format!("SELECT ({}), COUNT(c.theid) as count FROM ({}) c", CURRENT_REVISION_SQL, LIST_SQL.replace("{}", ""));
+        // Counting projects only ids so it never reads (or sorts) value blobs, which may spill
+        // into overflow pages.
+        pub static ref COUNT_SQL: String = format!("SELECT ({}), COUNT(kv.id) AS count
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0", CURRENT_REVISION_SQL);
         pub static ref GET_CURRENT_SQL: String = LIST_SQL.replace("{}", "");
+        // rtrim strips the trailing characters that aren't `/`, leaving the name up to its last
+        // `/`; replacing that with nothing leaves the last segment.
+        pub static ref LIST_NUMERIC_SQL: String = GET_CURRENT_SQL.replace("ORDER BY kv.id ASC", "ORDER BY CAST(replace(kv.name, rtrim(kv.name, replace(kv.name, '/', '')), '') AS INTEGER) ASC, kv.name ASC");
+        pub static ref MODIFIED_BETWEEN_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.mtime >= ? AND kv.mtime < ?
+            ORDER BY kv.id ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref LIST_STALE_SQL: String = MODIFIED_BETWEEN_SQL.replace("kv.mtime >= ? AND kv.mtime < ?", "kv.mtime < ?");
+        // kine leaves create_revision at 0 on the create row itself, meaning the row's own id.
+        pub static ref CREATED_SINCE_SQL: String = MODIFIED_BETWEEN_SQL.replace("kv.mtime >= ? AND kv.mtime < ?", "(CASE kv.create_revision WHEN 0 THEN kv.id ELSE kv.create_revision END) > ?");
+        pub static ref RANGE_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name >= ?
+                    {{}}
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.name != 'compact_rev_key'
+            ORDER BY kv.name ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref GET_MAP_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name IN ({{}}) AND mkv.name != 'compact_rev_key'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref CHANGES_SINCE_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\'
+                    AND mkv.id > ?
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.name != 'compact_rev_key'
+            ORDER BY kv.id ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref RECENT_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            WHERE kv.name != 'compact_rev_key'
+            ORDER BY kv.id DESC
+            LIMIT ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref LIST_EMPTY_VALUES_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\' AND mkv.name != 'compact_rev_key'
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.value_ref IS NULL AND (kv.value IS NULL OR LENGTH(kv.value) = 0)
+            ORDER BY kv.id ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref WATCH_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            WHERE
+                  kv.id > ? AND kv.name != 'compact_rev_key'
+            ORDER BY kv.id ASC
+            LIMIT ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref WATCH_PREFIX_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            WHERE
+                  kv.id > ? AND kv.name LIKE ? ESCAPE '\\' AND kv.name != 'compact_rev_key'
+            ORDER BY kv.id ASC
+            LIMIT ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref WATCH_KEY_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            WHERE
+                  kv.id > ? AND kv.name = ?
+            ORDER BY kv.id ASC
+            LIMIT ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref LIST_BY_CONTENT_TYPE_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.content_type = ?
+            ORDER BY kv.id ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref LIST_BY_LEASE_SQL: String = LIST_BY_CONTENT_TYPE_SQL.replace("kv.content_type = ?", "kv.lease = ?");
+        pub static ref RECENT_UNDER_SQL: String = GET_CURRENT_SQL.replace("ORDER BY kv.id ASC", "ORDER BY kv.id DESC LIMIT ?");
+        pub static ref SNAPSHOT_PAGE_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.id <= ?
+                    AND (? IS NULL OR mkv.name > ?)
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.name != 'compact_rev_key'
+            ORDER BY kv.name ASC
+            LIMIT ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref LIST_ANNOTATED_SQL: String = LIST_BY_TAG_SQL.replace("kv.tag = ?", "EXISTS (
+                    SELECT 1
+                    FROM annotations AS a
+                    WHERE a.revision = kv.id AND a.label = ? AND a.value = ?)");
+        pub static ref FLOOR_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name <= ?
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.name != 'compact_rev_key'
+            ORDER BY kv.name DESC
+            LIMIT 1", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref CEILING_SQL: String = FLOOR_SQL.replace("mkv.name <= ?", "mkv.name >= ?").replace("ORDER BY kv.name DESC", "ORDER BY kv.name ASC");
+        pub static ref TRANSFORM_PAGE_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                WHERE
+                    mkv.name LIKE ? ESCAPE '\\'
+                    AND (? IS NULL OR mkv.name > ?)
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.name != 'compact_rev_key'
+            ORDER BY kv.name ASC
+            LIMIT ?", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
+        pub static ref LIST_BY_TAG_SQL: String = format!("SELECT ({}), ({}), {}
+            FROM sumkin AS kv
+            JOIN (
+                SELECT MAX(mkv.id) AS id
+                FROM sumkin AS mkv
+                GROUP BY mkv.name) maxkv
+            ON maxkv.id = kv.id
+            WHERE
+                  kv.deleted = 0 AND kv.tag = ?
+            ORDER BY kv.id ASC", CURRENT_REVISION_SQL, COMPACT_REV_SQL, COLUMNS);
     }
 
 }
 
-static SCHEMA:  &'static [&'static str] = &[
+static SCHEMA: &[&str] = &[
     r###"
         CREATE TABLE IF NOT EXISTS sumkin
 			(
@@ -62,322 +499,5116 @@ static SCHEMA:  &'static [&'static str] = &[
     "CREATE UNIQUE INDEX IF NOT EXISTS sumkin_name_prev_revision_uindex ON sumkin (name, prev_revision)",
 ];
 
+/// Schema changes applied on top of `SCHEMA`. Entry `n` upgrades the database from
+/// `user_version` `n` to `n + 1`, so entries must only ever be appended.
+static MIGRATIONS: &[&str] = &[
+    "ALTER TABLE sumkin ADD COLUMN tag TEXT",
+    "CREATE INDEX IF NOT EXISTS sumkin_tag_index ON sumkin (tag)",
+    "ALTER TABLE sumkin ADD COLUMN mtime INTEGER",
+    "ALTER TABLE sumkin ADD COLUMN content_type TEXT",
+    "CREATE INDEX IF NOT EXISTS sumkin_content_type_index ON sumkin (content_type)",
+    r###"
+        CREATE TABLE IF NOT EXISTS leases
+            (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                ttl INTEGER,
+                expires_at INTEGER
+            )
+    "###,
+    // `name` was declared INTEGER, which stored numeric-looking keys as integers. SQLite can't
+    // change a column type in place, so rebuild the table, carrying over the AUTOINCREMENT
+    // counter so no revision is ever reused.
+    r###"
+        CREATE TABLE sumkin_new
+            (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT,
+                created INTEGER,
+                deleted INTEGER,
+                create_revision INTEGER,
+                prev_revision INTEGER,
+                lease INTEGER,
+                value BLOB,
+                old_value BLOB,
+                tag TEXT,
+                mtime INTEGER,
+                content_type TEXT
+            );
+        INSERT INTO sumkin_new(id, name, created, deleted, create_revision, prev_revision, lease, value, old_value, tag, mtime, content_type)
+            SELECT id, CAST(name AS TEXT), created, deleted, create_revision, prev_revision, lease, value, old_value, tag, mtime, content_type
+            FROM sumkin;
+        DELETE FROM sqlite_sequence WHERE name = 'sumkin_new';
+        INSERT INTO sqlite_sequence(name, seq) SELECT 'sumkin_new', seq FROM sqlite_sequence WHERE name = 'sumkin';
+        DROP TABLE sumkin;
+        ALTER TABLE sumkin_new RENAME TO sumkin;
+        CREATE INDEX sumkin_name_index ON sumkin (name);
+        CREATE INDEX sumkin_name_id_index ON sumkin (name,id);
+        CREATE INDEX sumkin_id_deleted_index ON sumkin (id,deleted);
+        CREATE INDEX sumkin_prev_revision_index ON sumkin (prev_revision);
+        CREATE UNIQUE INDEX sumkin_name_prev_revision_uindex ON sumkin (name, prev_revision);
+        CREATE INDEX sumkin_tag_index ON sumkin (tag);
+        CREATE INDEX sumkin_content_type_index ON sumkin (content_type);
+    "###,
+    r###"
+        CREATE TABLE IF NOT EXISTS requests
+            (
+                request_id TEXT PRIMARY KEY,
+                revision INTEGER
+            )
+    "###,
+    // Values stored out of line (see `SqliteConfig::blob_threshold`) leave `value` NULL and
+    // reference their blob by hash instead.
+    r###"
+        CREATE TABLE IF NOT EXISTS sumkin_blobs
+            (
+                hash TEXT PRIMARY KEY,
+                value BLOB
+            )
+    "###,
+    "ALTER TABLE sumkin ADD COLUMN value_ref TEXT",
+    "ALTER TABLE sumkin ADD COLUMN old_value_ref TEXT",
+    r###"
+        CREATE TABLE IF NOT EXISTS sumkin_meta
+            (
+                name TEXT PRIMARY KEY,
+                value INTEGER
+            )
+    "###,
+    r###"
+        CREATE TABLE IF NOT EXISTS revision_seq
+            (
+                value INTEGER NOT NULL
+            );
+        INSERT INTO revision_seq(value) VALUES(0);
+    "###,
+    r###"
+        CREATE TABLE IF NOT EXISTS annotations
+            (
+                name TEXT NOT NULL,
+                revision INTEGER NOT NULL,
+                label TEXT NOT NULL,
+                value TEXT,
+                PRIMARY KEY (name, revision, label)
+            );
+        CREATE INDEX IF NOT EXISTS annotations_label_index ON annotations (label, value);
+        CREATE INDEX IF NOT EXISTS annotations_revision_index ON annotations (revision);
+    "###,
+];
+
+/// The `mode` query parameter of an SQLite URI, if any.
+fn uri_mode(uri: &str) -> Option<&str> {
+    let (_, params) = uri.split_once('?')?;
+    params.split('&').find_map(|param| param.strip_prefix("mode="))
+}
+
+/// Recreates the empty log table with `name` collated `NOCASE`, so every comparison, grouping
+/// and index on names ignores ASCII case. Can't be applied once keys exist.
+static NOCASE_NAMES: &str = r###"
+    DROP TABLE sumkin;
+    CREATE TABLE sumkin
+        (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT COLLATE NOCASE,
+            created INTEGER,
+            deleted INTEGER,
+            create_revision INTEGER,
+            prev_revision INTEGER,
+            lease INTEGER,
+            value BLOB,
+            old_value BLOB,
+            tag TEXT,
+            mtime INTEGER,
+            content_type TEXT,
+            value_ref TEXT,
+            old_value_ref TEXT
+        );
+    CREATE INDEX sumkin_name_index ON sumkin (name);
+    CREATE INDEX sumkin_name_id_index ON sumkin (name,id);
+    CREATE INDEX sumkin_id_deleted_index ON sumkin (id,deleted);
+    CREATE INDEX sumkin_prev_revision_index ON sumkin (prev_revision);
+    CREATE UNIQUE INDEX sumkin_name_prev_revision_uindex ON sumkin (name, prev_revision);
+    CREATE INDEX sumkin_tag_index ON sumkin (tag);
+    CREATE INDEX sumkin_content_type_index ON sumkin (content_type);
+"###;
+
+/// Hex-encoded SHA-256 of `value`, the key of an out-of-line blob.
+fn blob_hash(value: &[u8]) -> String {
+    Sha256::digest(value).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The checksum `list_hashes` reports for each value.
+const VALUE_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
 fn create_file(path: &Path) -> SumkinResult<()> {
-    OpenOptions::new().write(true)
-                             .create_new(true)
-                             .open(path)?;
-    Ok(())
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e.into())
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct SqliteBackend {
-    pool: SqlitePool
+/// Tuning options for `SqliteBackend`. Pragmas set here are applied to every pooled connection.
+#[derive(Clone, Debug, Default)]
+pub struct SqliteConfig {
+    mmap_size: Option<i64>,
+    cache_size: Option<i64>,
+    busy_timeout: Option<Duration>,
+    foreign_keys: Option<bool>,
+    wal_autocheckpoint: Option<i64>,
+    per_key_retention: Option<u32>,
+    case_insensitive_keys: bool,
+    default_list_limit: Option<i64>,
+    max_list_limit: Option<i64>,
+    unbounded: bool,
+    warm_up: Option<u32>,
+    blob_threshold: Option<usize>,
+    revision_seq: bool,
+    hard_delete: bool,
+    prefix_mode: PrefixMode,
+    revision_warn_threshold: Option<Revision>,
+    max_revision: Option<Revision>,
+    max_concurrent_writes: Option<NonZeroUsize>,
+    refuse_newer_schema: bool,
+    consistent_read_timeout: Option<Duration>
 }
 
-impl SqliteBackend {
-    pub async fn new(filepath: &Path, pool_options: SqlitePoolOptions) -> SumkinResult<Self> {
-        info!("Connecting to datasource: {}", &filepath.display());
+impl SqliteConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-        create_file(filepath)?;
+    /// Maximum number of bytes to memory-map (`PRAGMA mmap_size`).
+    pub fn mmap_size(mut self, bytes: i64) -> Self {
+        self.mmap_size = Some(bytes);
+        self
+    }
+
+    /// Page cache size (`PRAGMA cache_size`): pages if positive, KiB if negative.
+    pub fn cache_size(mut self, size: i64) -> Self {
+        self.cache_size = Some(size);
+        self
+    }
+
+    /// How long a connection waits on a locked database before failing with `SQLITE_BUSY`.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = Some(timeout);
+        self
+    }
+
+    pub fn foreign_keys(mut self, on: bool) -> Self {
+        self.foreign_keys = Some(on);
+        self
+    }
+
+    /// Number of WAL pages after which SQLite checkpoints automatically
+    /// (`PRAGMA wal_autocheckpoint`, default 1000). Zero or negative disables it.
+    pub fn wal_autocheckpoint(mut self, pages: i64) -> Self {
+        self.wal_autocheckpoint = Some(pages);
+        self
+    }
+
+    /// Keeps at most `revisions` superseded rows per key, deleting older ones in the same
+    /// transaction as each `put` or update. This bounds the log without a compactor, at the cost
+    /// of history: reads, watches and `event_at` at a dropped revision find nothing, and nothing
+    /// records that the rows are gone the way `prune_log` does.
+    pub fn per_key_retention(mut self, revisions: u32) -> Self {
+        self.per_key_retention = Some(revisions);
+        self
+    }
+
+    /// Makes key names case-insensitive (ASCII only): `/User` and `/user` are the same key for
+    /// every lookup, list and count, and the key keeps the case it was first written with.
+    /// It's fixed in the schema: an empty database is converted on open, one already converted
+    /// is left as is, and opening one that holds case-sensitive keys fails with
+    /// `Error::SchemaMismatch`.
+    pub fn case_insensitive_keys(mut self, on: bool) -> Self {
+        self.case_insensitive_keys = on;
+        self
+    }
+
+    /// Limit used by list calls that ask for none (`limit <= 0`), still subject to
+    /// `max_list_limit`.
+    pub fn default_list_limit(mut self, limit: i64) -> Self {
+        self.default_list_limit = Some(limit);
+        self
+    }
+
+    /// Upper bound on how many keys a single list call returns. Calls asking for more, or
+    /// for no limit without a `default_list_limit`, are clamped with a warning. Guards against
+    /// accidental full scans of a large store.
+    pub fn max_list_limit(mut self, limit: i64) -> Self {
+        self.max_list_limit = Some(limit);
+        self
+    }
+
+    /// Opts out of the list limits for calls that ask for no limit, restoring full scans.
+    pub fn unbounded(mut self) -> Self {
+        self.unbounded = true;
+        self
+    }
+
+    /// Opens `connections` pooled connections and checks each with `SELECT 1` before the
+    /// constructor returns, so the first burst of requests doesn't pay for connecting and a
+    /// broken setup fails at startup. Pass the pool's `max_connections` to open all of them;
+    /// sqlx doesn't expose it to do this for you. Asking for more fails once the pool's acquire
+    /// timeout runs out.
+    pub fn warm_up(mut self, connections: u32) -> Self {
+        self.warm_up = Some(connections);
+        self
+    }
+
+    /// Stores values longer than `bytes` out of line, in a `sumkin_blobs` table keyed by their
+    /// SHA-256, so identical values are stored once and scans of the log table don't page
+    /// through large blobs. Only affects writes made from then on. Blobs no row refers to any
+    /// more are removed by `prune_log`, not by `per_key_retention`.
+    pub fn blob_threshold(mut self, bytes: usize) -> Self {
+        self.blob_threshold = Some(bytes);
+        self
+    }
+
+    /// Takes revisions from a counter in the `revision_seq` table, bumped in each write's
+    /// transaction, instead of from the log's AUTOINCREMENT row ids. Revisions then stay
+    /// sequential and keep increasing across a `clear`, which would otherwise restart them.
+    pub fn revision_seq(mut self, on: bool) -> Self {
+        self.revision_seq = on;
+        self
+    }
+
+    /// Makes deletes remove every row of the key instead of writing a tombstone, for stores
+    /// that need neither history nor watches. This breaks both: watchers never see the delete,
+    /// history of the key is gone, and since no row is written, the revision a delete returns
+    /// is consumed without appearing in the log: `current_revision` stays below it until the
+    /// next write, which gets a higher revision still. Out-of-line blobs
+    /// and annotations of removed rows are freed by the next `prune_log`.
+    pub fn hard_delete(mut self, on: bool) -> Self {
+        self.hard_delete = on;
+        self
+    }
+
+    /// What prefixes not ending in `/` select in list, count, watch, compaction and the other
+    /// prefix-taking calls; see `PrefixMode`. Defaults to `PrefixMode::Exact`, where `/root`
+    /// names only the key `/root`. `get` always reads exactly the key it's given.
+    pub fn prefix_mode(mut self, mode: PrefixMode) -> Self {
+        self.prefix_mode = mode;
+        self
+    }
+
+    /// Logs a warning on every write whose revision is at or past `threshold`, so operators
+    /// notice a store heading for `max_revision` long before it gets there.
+    pub fn revision_warn_threshold(mut self, threshold: Revision) -> Self {
+        self.revision_warn_threshold = Some(threshold);
+        self
+    }
+
+    /// Rejects, with `Error::RevisionLimit`, any write that would get a revision past `max`.
+    /// The write is rolled back, so the store is left read-only in practice until it's
+    /// cleared or the limit raised.
+    pub fn max_revision(mut self, max: Revision) -> Self {
+        self.max_revision = Some(max);
+        self
+    }
+
+    /// Lets at most `writes` write transactions run at once; further writes wait their turn
+    /// instead of racing for SQLite's single write lock and failing with busy or locked errors.
+    /// The limit is independent of the pool size, so reads aren't held up. With 1, writes
+    /// through this backend (and its clones) never contend with each other. A `begin`
+    /// transaction holds its slot until it's committed or dropped, and may be held for as long
+    /// as the caller likes; when open transactions hold every slot, writes fail with
+    /// `Error::WritesHeld` rather than wait, so writing through the backend while holding one
+    /// can't deadlock. Write through the transaction instead.
+    pub fn max_concurrent_writes(mut self, writes: NonZeroUsize) -> Self {
+        self.max_concurrent_writes = Some(writes);
+        self
+    }
+
+    /// Fails opening, with `Error::SchemaMismatch`, a database whose schema was written by a
+    /// newer version of this crate, instead of only logging a warning.
+    pub fn refuse_newer_schema(mut self, on: bool) -> Self {
+        self.refuse_newer_schema = on;
+        self
+    }
+
+    /// How long `get_consistent` waits for the store to reach the requested revision before
+    /// failing with `Error::Timeout`. Defaults to 5 seconds.
+    pub fn consistent_read_timeout(mut self, timeout: Duration) -> Self {
+        self.consistent_read_timeout = Some(timeout);
+        self
+    }
+
+    /// How this store reads a prefix argument.
+    fn prefix_match(&self, prefix: &str) -> PrefixMatch {
+        normalize_prefix_with(prefix, self.prefix_mode)
+    }
+
+    /// The limit a list call asking for `limit` actually uses; `0` means none.
+    fn list_limit(&self, limit: i64) -> i64 {
+        if limit <= 0 {
+            if self.unbounded {
+                return 0;
+            }
+            if let Some(default) = self.default_list_limit.filter(|default| *default > 0) {
+                return self.list_limit(default);
+            }
+        }
+        match self.max_list_limit {
+            Some(max) if limit <= 0 || limit > max => {
+                warn!("Clamping list limit {} to the maximum of {}", limit, max);
+                max
+            }
+            _ => limit.max(0)
+        }
+    }
+
+    /// Same as `list_limit`, for queries binding `LIMIT ?`, where none is `-1`.
+    fn bound_list_limit(&self, limit: i64) -> i64 {
+        match self.list_limit(limit) {
+            0 => -1,
+            limit => limit
+        }
+    }
+
+    /// Folds the settings into connect options. sqlx runs these on every connection the pool
+    /// opens, unlike statements executed once against the pool after connecting.
+    fn apply(&self, mut options: SqliteConnectOptions) -> SqliteConnectOptions {
+        if let Some(busy_timeout) = self.busy_timeout {
+            options = options.busy_timeout(busy_timeout);
+        }
+        if let Some(foreign_keys) = self.foreign_keys {
+            options = options.foreign_keys(foreign_keys);
+        }
+        if let Some(mmap_size) = self.mmap_size {
+            options = options.pragma("mmap_size", mmap_size.to_string());
+        }
+        if let Some(cache_size) = self.cache_size {
+            options = options.pragma("cache_size", cache_size.to_string());
+        }
+        if let Some(wal_autocheckpoint) = self.wal_autocheckpoint {
+            options = options.pragma("wal_autocheckpoint", wal_autocheckpoint.to_string());
+        }
+        // Prefix and exact lookups go through LIKE, which ignores ASCII case by default while
+        // writes compare names with `=`; keep both case-sensitive unless names are NOCASE.
+        let case_sensitive_like = if self.case_insensitive_keys { "OFF" } else { "ON" };
+        options.pragma("case_sensitive_like", case_sensitive_like)
+    }
+}
+
+/// How long a watch waits before polling the log again once it has caught up.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Maximum number of log rows a watch reads per poll; this bounds its buffer.
+const WATCH_BATCH_SIZE: i64 = 128;
+
+/// How long `get_consistent` waits for the store to reach the requested revision, unless
+/// `SqliteConfig::consistent_read_timeout` says otherwise.
+const CONSISTENT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of keys `transform_values` rewrites per transaction.
+const TRANSFORM_BATCH_SIZE: i64 = 100;
+
+/// Maximum number of names or ids bound in one query, well under SQLite's parameter limit.
+const GET_MAP_CHUNK_SIZE: usize = 500;
+
+/// Which keys a watch streams changes for.
+enum WatchFilter {
+    All,
+    Key(String),
+    /// Keys matching a `LIKE` pattern.
+    Like(String)
+}
+
+struct WatchState {
+    backend: SqliteBackend,
+    filter: WatchFilter,
+    last_revision: Option<Revision>,
+    pending: VecDeque<KeyValue>,
+    with_prev_kv: bool,
+    done: bool
+}
+
+/// Decodes an etcd-style `[key, range_end)` into text bounds; `None` means unbounded.
+fn range_bounds<'a>(key: &'a [u8], range_end: &[u8]) -> SumkinResult<(&'a str, Option<String>)> {
+    let start = std::str::from_utf8(key)?;
+    let end = match range_end {
+        [] => Some(format!("{}\0", start)),
+        [0] => None,
+        end => Some(std::str::from_utf8(end)?.to_owned()),
+    };
+    Ok((start, end))
+}
+
+fn range_end_predicate(end: &Option<String>) -> &'static str {
+    if end.is_some() { "AND mkv.name < ?" } else { "" }
+}
+
+/// A paged, read-only view of the keyspace as of a fixed revision, created by
+/// `SqliteBackend::snapshot`. Pages are ordered by key.
+#[derive(Debug)]
+pub struct Snapshot {
+    backend: SqliteBackend,
+    revision: Revision,
+    last_key: Option<String>
+}
+
+impl Snapshot {
+    /// The revision every page reflects.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Returns up to `limit` keys following the previous page, or an empty page once exhausted.
+    /// Fails with `Error::Compacted` if the log was compacted past the snapshot revision.
+    pub async fn next_page(&mut self, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        self.backend.ensure_not_compacted(self.revision).await?;
+        debug!("SNAPSHOT PAGE SQL: {}", sql::SNAPSHOT_PAGE_SQL.as_str());
+        let kvs = sqlx::query_as::<_, KeyValue>(sql::SNAPSHOT_PAGE_SQL.as_str())
+            .bind(self.revision)
+            .bind(&self.last_key)
+            .bind(&self.last_key)
+            .bind(self.backend.config.bound_list_limit(limit))
+            .fetch_all(&self.backend.pool).await?;
+        if let Some(kv) = kvs.last() {
+            self.last_key = Some(kv.key().clone());
+        }
+        Ok(kvs)
+    }
+}
+
+/// A caller-managed transaction from `SqliteBackend::begin`. Reads see its own writes, and
+/// nothing is visible to others until `commit`. Dropping it without committing rolls back.
+pub struct BatchTransaction {
+    backend: SqliteBackend,
+    tx: Transaction<'static, Sqlite>,
+    changes: Vec<(ChangeOp, String, Revision)>,
+    _permit: Option<TransactionPermit>
+}
+
+/// The write slots handed out under `max_concurrent_writes`.
+#[derive(Debug)]
+struct WriteSlots {
+    permits: Arc<Semaphore>,
+    limit: usize,
+    /// How many slots are held by open `begin` transactions, which nothing bounds in time.
+    held_by_transactions: AtomicUsize
+}
+
+/// A write slot held by a `BatchTransaction`, counted in `held_by_transactions` while held.
+struct TransactionPermit {
+    slots: Arc<WriteSlots>,
+    _permit: OwnedSemaphorePermit
+}
+
+impl Drop for TransactionPermit {
+    fn drop(&mut self) {
+        self.slots.held_by_transactions.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl BatchTransaction {
+    pub async fn get(&mut self, name: &str) -> SumkinResult<Option<KeyValue>> {
+        SqliteBackend::get_with_tx(&mut self.tx, name, None).await
+    }
+
+    pub async fn list_current(&mut self, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
+        let limit = self.backend.config.list_limit(limit);
+        SqliteBackend::list_current_with_tx(&mut self.tx, &self.backend.config.prefix_match(prefix), limit, include_deleted).await
+    }
+
+    pub async fn put(&mut self, name: &str, value: &[u8]) -> SumkinResult<Revision> {
+        let revision = self.backend.put_with_tx(&mut self.tx, name, value, None, None).await?;
+        self.changes.push((ChangeOp::Put, name.to_owned(), revision));
+        Ok(revision)
+    }
+
+    /// Same as `Backend::delete`: deleting a missing key writes nothing and returns the
+    /// current revision.
+    pub async fn delete(&mut self, name: &str) -> SumkinResult<Revision> {
+        match SqliteBackend::get_with_tx(&mut self.tx, name, None).await? {
+            Some(kv) => {
+                let revision = self.backend.tombstone_with_tx(&mut self.tx, &kv).await?;
+                self.changes.push((ChangeOp::Delete, name.to_owned(), revision));
+                Ok(revision)
+            }
+            None => SqliteBackend::current_revision_with_tx(&mut self.tx).await
+        }
+    }
+
+    pub async fn commit(self) -> SumkinResult<()> {
+        self.tx.commit().await?;
+        for (op, name, revision) in &self.changes {
+            self.backend.notify(*op, name, *revision);
+        }
+        Ok(())
+    }
+
+    pub async fn rollback(self) -> SumkinResult<()> {
+        self.tx.rollback().await?;
+        Ok(())
+    }
+}
+
+/// A read transaction from `SqliteBackend::read_txn`, pinned to one connection so every read
+/// sees the same state of the store, whatever is written meanwhile. It holds that connection
+/// and SQLite's read lock until `finish` or drop, so keep it short: on the shared cache `new`
+/// and `with_config` open, writers wait for it to end (with a private cache they go ahead, and
+/// it keeps reading its WAL snapshot).
+pub struct ReadTxn {
+    backend: SqliteBackend,
+    tx: Transaction<'static, Sqlite>,
+    revision: Revision
+}
+
+impl ReadTxn {
+    /// The current revision as of the start of the transaction, which every read reflects.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    pub async fn get(&mut self, name: &str) -> SumkinResult<Option<KeyValue>> {
+        SqliteBackend::get_with_tx(&mut self.tx, name, None).await
+    }
+
+    pub async fn list_current(&mut self, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
+        let limit = self.backend.config.list_limit(limit);
+        SqliteBackend::list_current_with_tx(&mut self.tx, &self.backend.config.prefix_match(prefix), limit, include_deleted).await
+    }
+
+    pub async fn count(&mut self, prefix: &str) -> SumkinResult<u64> {
+        debug!("COUNT SQL: {}", sql::COUNT_SQL.as_str());
+        let row = sqlx::query(sql::COUNT_SQL.as_str()).bind(self.backend.config.prefix_match(prefix).like_pattern()).fetch_one(&mut self.tx).await?;
+        let count: i64 = row.try_get("count")?;
+        Ok(count as u64)
+    }
+
+    /// Ends the transaction, releasing its connection.
+    pub async fn finish(self) -> SumkinResult<()> {
+        self.tx.rollback().await?;
+        Ok(())
+    }
+}
+
+/// A log row that breaks an invariant of the write path, found by
+/// `SqliteBackend::check_invariants`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// A create row whose create_revision isn't its own revision.
+    CreateRevisionMismatch { name: String, revision: Revision, create_revision: Revision },
+    /// A tombstone with no earlier revision of its key.
+    OrphanTombstone { name: String, revision: Revision },
+    /// A row whose prev_revision isn't below its own revision.
+    NonMonotonicPrevRevision { name: String, revision: Revision, prev_revision: Revision },
+}
+
+/// How often a held lock renews its lease, as a fraction of the lease ttl.
+const LOCK_KEEP_ALIVE_DIVISOR: u32 = 3;
+
+/// A lock acquired with `SqliteBackend::try_lock`. The lock key is attached to a lease that is
+/// kept alive in the background while the guard lives.
+///
+/// Dropping the guard releases the lock from a spawned task; use `release` to wait for it and
+/// see errors.
+#[derive(Debug)]
+pub struct LockGuard {
+    backend: SqliteBackend,
+    name: String,
+    lease_id: i64,
+    revision: Revision,
+    keep_alive: tokio::task::JoinHandle<()>,
+    released: bool
+}
+
+impl LockGuard {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn lease_id(&self) -> i64 {
+        self.lease_id
+    }
+
+    /// The revision that created the lock key.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Deletes the lock key, if this guard still holds it, and revokes its lease.
+    pub async fn release(mut self) -> SumkinResult<()> {
+        self.released = true;
+        self.keep_alive.abort();
+        self.backend.unlock(&self.name, self.lease_id).await
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.keep_alive.abort();
+        if self.released {
+            return;
+        }
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let backend = self.backend.clone();
+            let name = std::mem::take(&mut self.name);
+            let lease_id = self.lease_id;
+            handle.spawn(async move {
+                if let Err(e) = backend.unlock(&name, lease_id).await {
+                    warn!("Failed to release lock {}: {}", name, e);
+                }
+            });
+        } else {
+            warn!("Dropped lock {} outside of a runtime, it stays held", self.name);
+        }
+    }
+}
+
+/// Callback registered with `SqliteBackend::on_change`.
+pub type ChangeCallback = Arc<dyn Fn(ChangeEvent) + Send + Sync>;
+
+#[derive(Clone)]
+struct ChangeHook(ChangeCallback);
+
+impl std::fmt::Debug for ChangeHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChangeHook")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SqliteBackend {
+    pool: SqlitePool,
+    clock: Arc<dyn Clock>,
+    config: SqliteConfig,
+    on_change: Option<ChangeHook>,
+    write_slots: Option<Arc<WriteSlots>>
+}
+
+impl SqliteBackend {
+    pub async fn new(filepath: &Path, pool_options: SqlitePoolOptions) -> SumkinResult<Self> {
+        Self::with_config(filepath, pool_options, SqliteConfig::default()).await
+    }
+
+    pub async fn with_config(filepath: &Path, pool_options: SqlitePoolOptions, config: SqliteConfig) -> SumkinResult<Self> {
+        info!("Connecting to datasource: {}", &filepath.display());
+
+        create_file(filepath)?;
+
+        let options = SqliteConnectOptions::new()
+            .filename(filepath)
+            .journal_mode(SqliteJournalMode::Wal)
+            .shared_cache(true);
+        let options = config.apply(options);
+        let pool = pool_options.connect_with(options).await?;
+
+        if config.refuse_newer_schema {
+            if let Some(version) = Self::stored_version_of(&pool).await?.filter(|version| *version > Self::schema_version()) {
+                pool.close().await;
+                return Err(Error::SchemaMismatch {
+                    reason: format!("schema version {} is newer than the supported {}", version, Self::schema_version())
+                });
+            }
+        }
+
+        debug!("Connecting to datasource: {}", &filepath.display());
+        let mut backend = Self::with_pool(pool).await?;
+        if config.case_insensitive_keys {
+            backend.collate_names_nocase().await?;
+        }
+        if let Some(connections) = config.warm_up {
+            backend.warm_up(connections).await?;
+        }
+        backend.write_slots = config.max_concurrent_writes.map(|writes| Arc::new(WriteSlots {
+            permits: Arc::new(Semaphore::new(writes.get())),
+            limit: writes.get(),
+            held_by_transactions: AtomicUsize::new(0)
+        }));
+        backend.config = config;
+        Ok(backend)
+    }
+
+    /// Opens the database named by an SQLite URI such as `file:state.db?mode=ro&cache=private`
+    /// (`sqlite:` URLs work too), taking journal mode, cache and open mode from the URI as is
+    /// rather than applying the defaults `new` uses. Without a `mode` the file is created if
+    /// missing, like `new`; `ro`, `rw` and `memory` never create one.
+    pub async fn from_uri(uri: &str, pool_options: SqlitePoolOptions) -> SumkinResult<Self> {
+        info!("Connecting to datasource: {}", uri);
+        let mut options = SqliteConnectOptions::from_str(uri.trim_start_matches("file:"))?;
+        if uri_mode(uri).is_none() {
+            options = options.create_if_missing(true);
+        }
+        let options = options.pragma("case_sensitive_like", "ON");
+        let pool = pool_options.connect_with(options).await?;
+        Self::with_pool(pool).await
+    }
+
+    /// Uses an existing pool as is. Connection-level settings (journal mode, pragmas) are
+    /// whatever the pool was built with; use `with_config` to have them applied for you.
+    /// Without `case_sensitive_like` on, reads match names ignoring ASCII case but writes
+    /// don't.
+    pub async fn with_pool(pool: SqlitePool) -> SumkinResult<Self> {
+        info!("Configuring database table schema and indexes, this may take a moment...");
+
+        Self::apply_schema(&pool, SCHEMA, MIGRATIONS).await?;
+        Self::import_kine(&pool).await?;
+        info!("Backend setup complete.");
+        Ok(Self {
+            pool,
+            clock: Arc::new(SystemClock),
+            config: SqliteConfig::default(),
+            on_change: None,
+            write_slots: None
+        })
+
+    }
+
+    /// Applies `schema` and any `migrations` past the stored user_version in one transaction,
+    /// so a failure part way leaves the database as it was. SQLite runs DDL and `user_version`
+    /// updates transactionally; statements that can't run inside a transaction (`VACUUM`,
+    /// `journal_mode`) don't belong in either list.
+    async fn apply_schema(pool: &SqlitePool, schema: &[&str], migrations: &[&str]) -> SumkinResult<()> {
+        let mut tx = pool.begin().await?;
+        for migration in schema {
+            debug!("Running migration : {}", migration);
+            if let Err(e) = tx.execute(*migration).await {
+                warn!("Schema setup failed, rolling back: {}", e);
+                return Err(e.into());
+            }
+        }
+
+        let user_version: i64 = sqlx::query(sql::USER_VERSION_SQL).fetch_one(&mut tx).await?.try_get(0)?;
+        if user_version as usize > migrations.len() {
+            warn!("Database schema version {} is newer than the supported {}, it was written by a newer version", user_version, migrations.len());
+        }
+        for (version, migration) in migrations.iter().enumerate().skip(user_version as usize) {
+            debug!("Running migration {}: {}", version + 1, migration);
+            if let Err(e) = tx.execute(*migration).await {
+                warn!("Migration {} failed, rolling back: {}", version + 1, e);
+                return Err(e.into());
+            }
+            tx.execute(format!("PRAGMA user_version = {}", version + 1).as_str()).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Recreates the log table with `NOCASE` names unless they already are. Only an empty log
+    /// can be converted, as existing keys could collide once case is ignored.
+    async fn collate_names_nocase(&self) -> SumkinResult<()> {
+        let mut tx = self.pool.begin().await?;
+        debug!("LOG TABLE SQL: {}", sql::LOG_TABLE_SQL);
+        let table: String = sqlx::query(sql::LOG_TABLE_SQL).fetch_one(&mut tx).await?.try_get(0)?;
+        if table.contains("COLLATE NOCASE") {
+            return Ok(());
+        }
+        let empty: bool = sqlx::query(sql::IS_LOG_EMPTY_SQL).fetch_one(&mut tx).await?.try_get(0)?;
+        if !empty {
+            return Err(Error::SchemaMismatch { reason: "case-insensitive keys need an empty database".to_string() });
+        }
+        debug!("Collating names NOCASE: {}", NOCASE_NAMES);
+        tx.execute(NOCASE_NAMES).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Holds `count` connections at once, so each one is actually opened (running the
+    /// connect-time pragmas), and checks each with a trivial query. They stay idle in the pool
+    /// afterwards.
+    async fn warm_up(&self, count: u32) -> SumkinResult<()> {
+        let mut connections = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut conn = self.pool.acquire().await?;
+            debug!("WARM UP SQL: {}", sql::WARM_UP_SQL);
+            sqlx::query(sql::WARM_UP_SQL).execute(&mut conn).await?;
+            connections.push(conn);
+        }
+        info!("Warmed up {} connections.", connections.len());
+        Ok(())
+    }
+
+    /// Adopts the log of a k3s/kine SQLite database found in the same file.
+    ///
+    /// kine's `kine` table has the same layout as the original `sumkin` table, so rows are copied
+    /// column for column, keeping `id` and therefore every revision:
+    ///
+    /// | kine              | sumkin            |
+    /// |-------------------|-------------------|
+    /// | `id`              | `id`              |
+    /// | `name`            | `name`            |
+    /// | `created`         | `created`         |
+    /// | `deleted`         | `deleted`         |
+    /// | `create_revision` | `create_revision` |
+    /// | `prev_revision`   | `prev_revision`   |
+    /// | `lease`           | `lease`           |
+    /// | `value`           | `value`           |
+    /// | `old_value`       | `old_value`       |
+    ///
+    /// `tag` and `mtime` are left `NULL`. kine's `compact_rev_key` row carries over as the
+    /// compaction point. The import only runs while `sumkin` is empty and leaves `kine` untouched.
+    async fn import_kine(pool: &SqlitePool) -> SumkinResult<()> {
+        let mut tx = pool.begin().await?;
+        let has_kine: i64 = sqlx::query(sql::KINE_TABLE_EXISTS_SQL).fetch_one(&mut tx).await?.try_get(0)?;
+        let log_is_empty: bool = sqlx::query(sql::LOG_IS_EMPTY_SQL).fetch_one(&mut tx).await?.try_get(0)?;
+        if has_kine > 0 && log_is_empty {
+            info!("Found a kine table, importing its log...");
+            debug!("IMPORT KINE SQL: {}", sql::IMPORT_KINE_SQL);
+            let imported = sqlx::query(sql::IMPORT_KINE_SQL).execute(&mut tx).await?.rows_affected();
+            info!("Imported {} rows from kine.", imported);
+            Self::bump_generation_with_tx(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Streams every change across the whole keyspace in revision order, starting at
+    /// `start_revision` (inclusive), or with the next write if `start_revision <= 0`.
+    ///
+    /// The log is polled lazily as the stream is consumed, so a slow consumer holds at most one
+    /// batch of events in memory. If the log gets compacted past the watch position the stream
+    /// yields `WatchEvent::Compacted` and ends; it also ends after yielding an error.
+    ///
+    /// With `with_prev_kv`, each put and delete also carries the revision of the key it
+    /// replaced, like etcd's `prev_kv`, at the cost of one extra lookup per event. It's `None`
+    /// for creates and when that revision has been pruned.
+    pub fn watch_all(&self, start_revision: Revision, with_prev_kv: bool) -> impl Stream<Item = SumkinResult<WatchEvent>> + Send {
+        self.watch(start_revision, WatchFilter::All, with_prev_kv)
+    }
+
+    /// Same as `watch_all`, but only streams changes to the exact key `name`.
+    pub fn watch_key(&self, name: &str, start_revision: Revision, with_prev_kv: bool) -> impl Stream<Item = SumkinResult<WatchEvent>> + Send {
+        self.watch(start_revision, WatchFilter::Key(name.to_owned()), with_prev_kv)
+    }
+
+    /// Waits until `name` exists with a value for which `pred` holds, and returns it. The current
+    /// value is checked first; after that every put is checked as it is streamed by a
+    /// `watch_key`, so each intermediate value is seen, not just whatever is current when
+    /// polled. Fails with `Error::Timeout` once `timeout` passes.
+    pub async fn wait_for<F>(&self, name: &str, pred: F, timeout: Duration) -> SumkinResult<KeyValue>
+        where F: Fn(&KeyValue) -> bool
+    {
+        match tokio::time::timeout(timeout, self.wait_until(name, &pred)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::Timeout { after: timeout })
+        }
+    }
+
+    async fn wait_until<F>(&self, name: &str, pred: &F) -> SumkinResult<KeyValue>
+        where F: Fn(&KeyValue) -> bool
+    {
+        // Each pass reads the current state and watches from just after it; a watch only ends
+        // early when it falls behind compaction, and then the next pass resyncs.
+        loop {
+            let mut tx = self.pool.begin().await?;
+            let revision = Self::current_revision_with_tx(&mut tx).await?;
+            let kv = Self::get_with_tx(&mut tx, name, None).await?;
+            tx.commit().await?;
+            if let Some(kv) = kv.filter(|kv| pred(kv)) {
+                return Ok(kv);
+            }
+            let events = self.watch_key(name, revision.next(), false);
+            tokio::pin!(events);
+            while let Some(event) = events.next().await {
+                if let WatchEvent::Put { kv, .. } = event? {
+                    if pred(&kv) {
+                        return Ok(kv);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as `watch_all`, but only streams changes to keys matching `prefix`, with the same
+    /// prefix semantics as `list_current`.
+    pub fn watch_prefix(&self, prefix: &str, start_revision: Revision, with_prev_kv: bool) -> impl Stream<Item = SumkinResult<WatchEvent>> + Send {
+        let filter = match self.config.prefix_match(prefix) {
+            PrefixMatch::Exact(name) => WatchFilter::Key(name),
+            prefix => WatchFilter::Like(prefix.like_pattern())
+        };
+        self.watch(start_revision, filter, with_prev_kv)
+    }
+
+    /// Same as `watch_prefix`, with each event formatted as a server-sent events frame,
+    /// `data: {json}\n\n`. Puts and deletes carry the key as exported by `export_ndjson`
+    /// under `"type": "put"` or `"delete"`; compaction is `{"type": "compacted", ...}`.
+    pub fn watch_sse(&self, prefix: &str, start_revision: Revision) -> impl Stream<Item = SumkinResult<Bytes>> + Send {
+        self.watch_prefix(prefix, start_revision, false).map(|event| {
+            let event = event?;
+            let mut frame = b"data: ".to_vec();
+            serde_json::to_writer(&mut frame, &Event::from(&event)).map_err(std::io::Error::from)?;
+            frame.extend_from_slice(b"\n\n");
+            Ok(Bytes::from(frame))
+        })
+    }
+
+    fn watch(&self, start_revision: Revision, filter: WatchFilter, with_prev_kv: bool) -> impl Stream<Item = SumkinResult<WatchEvent>> + Send {
+        let state = WatchState {
+            backend: self.clone(),
+            filter,
+            last_revision: if start_revision > Revision::default() { Some(start_revision.prev()) } else { None },
+            pending: VecDeque::new(),
+            with_prev_kv,
+            done: false
+        };
+        stream::unfold(state, |mut state| async move {
+            loop {
+                if state.done {
+                    return None;
+                }
+                if let Some(kv) = state.pending.pop_front() {
+                    let prev_kv = match *kv.prev_revision() {
+                        Some(prev_revision) if state.with_prev_kv => match state.backend.event_at(prev_revision).await {
+                            Ok(prev_kv) => prev_kv,
+                            Err(e) => {
+                                state.done = true;
+                                return Some((Err(e), state));
+                            }
+                        },
+                        _ => None
+                    };
+                    let event = if *kv.deleted() {
+                        WatchEvent::Delete { kv, prev_kv }
+                    } else {
+                        WatchEvent::Put { kv, prev_kv }
+                    };
+                    return Some((Ok(event), state));
+                }
+                let polled = match state.last_revision {
+                    Some(last_revision) => match state.backend.compact_revision().await {
+                        Ok(compact_revision) if compact_revision > last_revision => {
+                            state.done = true;
+                            return Some((Ok(WatchEvent::Compacted { compact_revision }), state));
+                        }
+                        Ok(_) => state.backend.poll_log(last_revision, &state.filter).await,
+                        Err(e) => Err(e),
+                    },
+                    None => state.backend.current_revision().await.map(|revision| {
+                        state.last_revision = Some(revision);
+                        Vec::new()
+                    }),
+                };
+                match polled {
+                    Ok(kvs) if kvs.is_empty() => tokio::time::sleep(WATCH_POLL_INTERVAL).await,
+                    Ok(kvs) => {
+                        state.last_revision = kvs.last().map(|kv| *kv.mod_revision());
+                        state.pending.extend(kvs);
+                    }
+                    Err(e) => {
+                        state.done = true;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+        })
+    }
+
+    async fn poll_log(&self, after: Revision, filter: &WatchFilter) -> SumkinResult<Vec<KeyValue>> {
+        let kvs = match filter {
+            WatchFilter::Key(name) => {
+                debug!("WATCH KEY SQL: {}", sql::WATCH_KEY_SQL.as_str());
+                sqlx::query_as::<_, KeyValue>(sql::WATCH_KEY_SQL.as_str())
+                    .bind(after)
+                    .bind(name)
+                    .bind(WATCH_BATCH_SIZE)
+                    .fetch_all(&self.pool).await?
+            }
+            WatchFilter::Like(pattern) => {
+                debug!("WATCH PREFIX SQL: {}", sql::WATCH_PREFIX_SQL.as_str());
+                sqlx::query_as::<_, KeyValue>(sql::WATCH_PREFIX_SQL.as_str())
+                    .bind(after)
+                    .bind(pattern)
+                    .bind(WATCH_BATCH_SIZE)
+                    .fetch_all(&self.pool).await?
+            }
+            WatchFilter::All => {
+                debug!("WATCH SQL: {}", sql::WATCH_SQL.as_str());
+                sqlx::query_as::<_, KeyValue>(sql::WATCH_SQL.as_str())
+                    .bind(after)
+                    .bind(WATCH_BATCH_SIZE)
+                    .fetch_all(&self.pool).await?
+            }
+        };
+        Ok(kvs)
+    }
+
+    /// Writes every current key under `prefix` to `writer` as newline-delimited JSON, one object
+    /// per key in revision order, and returns how many were written. Values are base64-encoded.
+    pub async fn export_ndjson<W: AsyncWrite + Unpin>(&self, prefix: &str, mut writer: W) -> SumkinResult<u64> {
+        debug!("LIST SQL: {}", sql::GET_CURRENT_SQL.as_str());
+        let mut rows = sqlx::query_as::<_, KeyValue>(sql::GET_CURRENT_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(false)
+            .fetch(&self.pool);
+        let mut count = 0;
+        let mut line = Vec::new();
+        while let Some(kv) = rows.try_next().await? {
+            line.clear();
+            serde_json::to_writer(&mut line, &Record::from(&kv)).map_err(std::io::Error::from)?;
+            line.push(b'\n');
+            writer.write_all(&line).await?;
+            count += 1;
+        }
+        writer.flush().await?;
+        Ok(count)
+    }
+
+    /// Reads newline-delimited JSON records in the format written by `export_ndjson` and puts
+    /// each one, keeping its tag and content type, in a single transaction. Returns how many
+    /// were imported. Recorded revisions are ignored; keys get new revisions here. Blank lines
+    /// are skipped, and any malformed line fails the whole import with `Error::InvalidRecord`.
+    pub async fn import_ndjson<R: AsyncRead + Unpin>(&self, reader: R) -> SumkinResult<u64> {
+        let mut lines = BufReader::new(reader).lines();
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let mut line_number = 0;
+        let mut written = Vec::new();
+        while let Some(line) = lines.next_line().await? {
+            line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |reason: String| Error::InvalidRecord { line: line_number, reason };
+            let record: Record = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+            let value = record.decode_value().map_err(|e| invalid(e.to_string()))?;
+            let revision = self.put_with_tx(&mut tx, &record.key, &value, record.tag.as_deref(), record.content_type.as_deref()).await?;
+            written.push((record.key, revision));
+        }
+        if !written.is_empty() {
+            Self::bump_generation_with_tx(&mut tx).await?;
+        }
+        tx.commit().await?;
+        for (name, revision) in &written {
+            self.notify(ChangeOp::Put, name, *revision);
+        }
+        Ok(written.len() as u64)
+    }
+
+    /// Rewrites the current value of every key under `prefix` as `f(value)`, keeping each key's
+    /// tag and content type, and returns the number of keys rewritten. Keys are processed in
+    /// name order, `TRANSFORM_BATCH_SIZE` per transaction, so a failure leaves earlier batches
+    /// applied; keys written concurrently may be transformed before or after that write.
+    pub async fn transform_values<F>(&self, prefix: &str, mut f: F) -> SumkinResult<u64>
+        where F: FnMut(&[u8]) -> Vec<u8>
+    {
+        let pattern = self.config.prefix_match(prefix).like_pattern();
+        let mut last_key: Option<String> = None;
+        let mut transformed = 0;
+        loop {
+            let _permit = self.write_permit().await?;
+            let mut tx = self.pool.begin().await?;
+            debug!("TRANSFORM PAGE SQL: {}", sql::TRANSFORM_PAGE_SQL.as_str());
+            let kvs = sqlx::query_as::<_, KeyValue>(sql::TRANSFORM_PAGE_SQL.as_str())
+                .bind(&pattern)
+                .bind(&last_key)
+                .bind(&last_key)
+                .bind(TRANSFORM_BATCH_SIZE)
+                .fetch_all(&mut tx).await?;
+            let mut written = Vec::with_capacity(kvs.len());
+            for kv in &kvs {
+                let value = f(kv.value().as_deref().unwrap_or_default());
+                let revision = self.update_with_tx(&mut tx, kv, &value, kv.tag().as_deref(), kv.content_type().as_deref()).await?;
+                written.push((kv.key(), revision));
+            }
+            tx.commit().await?;
+            for (name, revision) in written {
+                self.notify(ChangeOp::Put, name, revision);
+            }
+            transformed += kvs.len() as u64;
+            match kvs.last() {
+                Some(kv) if kvs.len() as i64 == TRANSFORM_BATCH_SIZE => last_key = Some(kv.key().clone()),
+                _ => break
+            }
+        }
+        info!("Transformed {} values under {}", transformed, prefix);
+        Ok(transformed)
+    }
+
+    /// Takes the lock `name` if no one holds it, by creating the key attached to a new lease of
+    /// `ttl_seconds`. Returns `None` if the key already exists.
+    ///
+    /// Expired leases are revoked first, in the same transaction, so a lock whose holder went
+    /// away without releasing it can be taken once its lease has run out. The guard renews the
+    /// lease until it's released or dropped.
+    pub async fn try_lock(&self, name: &str, ttl_seconds: i64) -> SumkinResult<Option<LockGuard>> {
+        let permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let (_, deleted) = self.revoke_expired_with_tx(&mut tx).await?;
+        // The attempt runs in a savepoint, so failing to take the lock keeps only the expiries.
+        let mut attempt = tx.begin().await?;
+        let lease_id = self.grant_lease_with_tx(&mut attempt, ttl_seconds).await?;
+        let id = self.next_revision_with_tx(&mut attempt).await?;
+        debug!("CREATE IF ABSENT SQL: {}", sql::CREATE_IF_ABSENT_SQL);
+        let created = sqlx::query(sql::CREATE_IF_ABSENT_SQL)
+            .bind(id)
+            .bind(name)
+            .bind(lease_id)
+            .bind(&[][..])
+            .bind(self.clock.now_millis())
+            .bind(None::<String>)
+            .bind(name)
+            .execute(&mut attempt).await?;
+        if created.rows_affected() == 0 {
+            attempt.rollback().await?;
+            tx.commit().await?;
+            drop(permit);
+            for (name, revision) in deleted {
+                self.notify(ChangeOp::Delete, &name, revision);
+            }
+            return Ok(None);
+        }
+        let revision = created.last_insert_rowid().into();
+        self.check_revision(revision)?;
+        Self::set_create_revision_with_tx(&mut attempt, revision).await?;
+        attempt.commit().await?;
+        tx.commit().await?;
+        drop(permit);
+        for (name, revision) in deleted {
+            self.notify(ChangeOp::Delete, &name, revision);
+        }
+        self.notify(ChangeOp::Put, name, revision);
+
+        let backend = self.clone();
+        let period = Duration::from_secs(ttl_seconds.max(1) as u64) / LOCK_KEEP_ALIVE_DIVISOR;
+        let keep_alive = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = backend.keep_alive(lease_id).await {
+                    warn!("Failed to keep lease {} alive: {}", lease_id, e);
+                }
+            }
+        });
+        Ok(Some(LockGuard {
+            backend: self.clone(),
+            name: name.to_owned(),
+            lease_id,
+            revision,
+            keep_alive,
+            released: false
+        }))
+    }
+
+    async fn unlock(&self, name: &str, lease_id: i64) -> SumkinResult<()> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let mut deleted = None;
+        if let Some(kv) = Self::get_with_tx(&mut tx, name, None).await? {
+            if *kv.lease() == Some(lease_id) {
+                deleted = Some(self.tombstone_with_tx(&mut tx, &kv).await?);
+            }
+        }
+        debug!("REVOKE LEASE SQL: {}", sql::REVOKE_LEASE_SQL);
+        sqlx::query(sql::REVOKE_LEASE_SQL).bind(lease_id).execute(&mut tx).await?;
+        tx.commit().await?;
+        if let Some(revision) = deleted {
+            self.notify(ChangeOp::Delete, name, revision);
+        }
+        Ok(())
+    }
+
+    /// Revokes every lease whose expiry, by the backend's clock, has passed, deleting the keys
+    /// attached to it, and returns the revoked lease ids. Nothing calls this on its own; run it
+    /// periodically to have leases expire.
+    pub async fn revoke_expired(&self) -> SumkinResult<Vec<i64>> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let (expired, deleted) = self.revoke_expired_with_tx(&mut tx).await?;
+        tx.commit().await?;
+        for (name, revision) in deleted {
+            self.notify(ChangeOp::Delete, &name, revision);
+        }
+        Ok(expired)
+    }
+
+    /// Revokes the expired leases and deletes their keys, returning the lease ids and the
+    /// deletes to notify once committed.
+    async fn revoke_expired_with_tx(&self, tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<(Vec<i64>, Vec<(String, Revision)>)> {
+        debug!("EXPIRED LEASES SQL: {}", sql::EXPIRED_LEASES_SQL);
+        let expired: Vec<i64> = sqlx::query_scalar(sql::EXPIRED_LEASES_SQL)
+            .bind(self.clock.now_millis())
+            .fetch_all(&mut *tx).await?;
+        let mut deleted = Vec::new();
+        for lease_id in &expired {
+            debug!("LIST BY LEASE SQL: {}", sql::LIST_BY_LEASE_SQL.as_str());
+            let kvs = sqlx::query_as::<_, KeyValue>(sql::LIST_BY_LEASE_SQL.as_str())
+                .bind(lease_id)
+                .fetch_all(&mut *tx).await?;
+            for kv in kvs {
+                let revision = self.tombstone_with_tx(tx, &kv).await?;
+                deleted.push((kv.key().clone(), revision));
+            }
+            debug!("REVOKE LEASE SQL: {}", sql::REVOKE_LEASE_SQL);
+            sqlx::query(sql::REVOKE_LEASE_SQL).bind(lease_id).execute(&mut *tx).await?;
+        }
+        if !expired.is_empty() {
+            info!("Revoked {} expired leases", expired.len());
+        }
+        Ok((expired, deleted))
+    }
+
+    /// Starts a transaction for the caller to fill and then commit or roll back.
+    pub async fn begin(&self) -> SumkinResult<BatchTransaction> {
+        let permit = self.write_permit().await?.map(|permit| {
+            let slots = self.write_slots.clone().expect("a permit comes from write slots");
+            slots.held_by_transactions.fetch_add(1, Ordering::SeqCst);
+            TransactionPermit { slots, _permit: permit }
+        });
+        Ok(BatchTransaction {
+            backend: self.clone(),
+            tx: self.pool.begin().await?,
+            changes: Vec::new(),
+            _permit: permit
+        })
+    }
+
+    /// Starts a read transaction, for several reads that must agree with each other.
+    pub async fn read_txn(&self) -> SumkinResult<ReadTxn> {
+        let mut tx = self.pool.begin().await?;
+        // A deferred transaction only takes its snapshot at the first read.
+        let revision = Self::current_revision_with_tx(&mut tx).await?;
+        Ok(ReadTxn {
+            backend: self.clone(),
+            tx,
+            revision
+        })
+    }
+
+    /// Scans the whole log for rows that break the write path's invariants, in revision order.
+    /// A healthy database returns nothing.
+    pub async fn check_invariants(&self) -> SumkinResult<Vec<InvariantViolation>> {
+        debug!("CHECK INVARIANTS SQL: {}", sql::CHECK_INVARIANTS_SQL);
+        let rows = sqlx::query(sql::CHECK_INVARIANTS_SQL).fetch_all(&self.pool).await?;
+        let mut violations = Vec::with_capacity(rows.len());
+        for row in rows {
+            let kind: String = row.try_get("kind")?;
+            let name: String = row.try_get("name")?;
+            let revision: Revision = row.try_get("id")?;
+            let other: Option<Revision> = row.try_get("other")?;
+            let other = other.unwrap_or_default();
+            violations.push(match kind.as_str() {
+                "create_revision" => InvariantViolation::CreateRevisionMismatch { name, revision, create_revision: other },
+                "orphan_tombstone" => InvariantViolation::OrphanTombstone { name, revision },
+                _ => InvariantViolation::NonMonotonicPrevRevision { name, revision, prev_revision: other },
+            });
+        }
+        Ok(violations)
+    }
+
+    /// Finds keys that were created more than once without a delete in between, which is what
+    /// two racing creates that both missed each other leave behind. Returns each such key with
+    /// the revisions of its creates, in name order. A healthy database returns nothing.
+    pub async fn find_duplicates(&self) -> SumkinResult<Vec<(String, Vec<Revision>)>> {
+        debug!("FIND DUPLICATES SQL: {}", sql::FIND_DUPLICATES_SQL);
+        let rows = sqlx::query(sql::FIND_DUPLICATES_SQL).fetch_all(&self.pool).await?;
+        let mut duplicates: Vec<(String, Vec<Revision>)> = Vec::new();
+        for row in rows {
+            let name: String = row.try_get("name")?;
+            let revision: Revision = row.try_get("id")?;
+            match duplicates.last_mut() {
+                Some((last, revisions)) if *last == name => revisions.push(revision),
+                _ => duplicates.push((name, vec![revision]))
+            }
+        }
+        Ok(duplicates)
+    }
+
+    /// The schema version this build creates and upgrades databases to.
+    pub fn schema_version() -> u32 {
+        MIGRATIONS.len() as u32
+    }
+
+    /// The schema version recorded in the database, or `None` if there is none. It's the
+    /// `schema_version` of the newest build that opened the database, so one higher than
+    /// `schema_version` means a newer build wrote it.
+    pub async fn stored_version(&self) -> SumkinResult<Option<u32>> {
+        Self::stored_version_of(&self.pool).await
+    }
+
+    async fn stored_version_of(pool: &SqlitePool) -> SumkinResult<Option<u32>> {
+        debug!("USER VERSION SQL: {}", sql::USER_VERSION_SQL);
+        let version: i64 = sqlx::query(sql::USER_VERSION_SQL).fetch_one(pool).await?.try_get(0)?;
+        Ok(Some(version as u32).filter(|version| *version > 0))
+    }
+
+    /// Same as `get`, but first waits until the store has reached `min_revision`, so a client
+    /// passing the revision returned by its own write is guaranteed to read it back. Any
+    /// revision a write returns serves as the token. With a single database file a committed
+    /// revision is visible at once, so this only waits for a revision not yet written, such as
+    /// one a concurrent writer is about to commit; setups reading from a replica or a separate
+    /// read pool also wait out the lag. Fails with `Error::Timeout` if `min_revision` isn't
+    /// reached within `SqliteConfig::consistent_read_timeout`.
+    pub async fn get_consistent(&self, name: &str, min_revision: Revision) -> SumkinResult<Option<KeyValue>> {
+        let timeout = self.config.consistent_read_timeout.unwrap_or(CONSISTENT_READ_TIMEOUT);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let mut tx = self.pool.begin().await?;
+            if Self::current_revision_with_tx(&mut tx).await? >= min_revision {
+                let kv = Self::get_with_tx(&mut tx, name, None).await?;
+                tx.commit().await?;
+                return Ok(kv);
+            }
+            tx.rollback().await?;
+            if tokio::time::Instant::now() >= deadline {
+                return Err(Error::Timeout { after: timeout });
+            }
+            tokio::time::sleep(WATCH_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Pins a consistent view of the keyspace at `revision`, or at the current revision if `None`.
+    pub async fn snapshot(&self, revision: Option<Revision>) -> SumkinResult<Snapshot> {
+        let revision = match revision {
+            Some(revision) => revision,
+            None => self.current_revision().await?,
+        };
+        self.ensure_not_compacted(revision).await?;
+        Ok(Snapshot {
+            backend: self.clone(),
+            revision,
+            last_key: None
+        })
+    }
+
+    async fn ensure_not_compacted(&self, revision: Revision) -> SumkinResult<()> {
+        let compact_revision = self.compact_revision().await?;
+        if revision < compact_revision {
+            return Err(Error::Compacted { revision, compact_revision });
+        }
+        Ok(())
+    }
+
+    /// Bytes used per table and index, from the `dbstat` virtual table. Returns an empty list
+    /// if SQLite was built without `dbstat`.
+    pub async fn size_breakdown(&self) -> SumkinResult<Vec<(String, u64)>> {
+        debug!("SIZE BREAKDOWN SQL: {}", sql::SIZE_BREAKDOWN_SQL);
+        let rows = match sqlx::query(sql::SIZE_BREAKDOWN_SQL).fetch_all(&self.pool).await {
+            Ok(rows) => rows,
+            Err(sqlx::Error::Database(e)) if e.message().contains("no such table: dbstat") => {
+                warn!("dbstat is not available in this SQLite build, can't break down size");
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        rows.iter()
+            .map(|row| {
+                let size: i64 = row.try_get("size")?;
+                Ok((row.try_get("name")?, size as u64))
+            })
+            .collect()
+    }
+
+    /// Checkpoints the WAL into the database file and syncs both, so every write committed
+    /// before the call is on disk even with `synchronous=NORMAL`. Fails with
+    /// `Error::CheckpointBusy` if other connections kept it from finishing within the busy
+    /// timeout. Outside WAL mode there is nothing to checkpoint and this returns at once.
+    pub async fn sync(&self) -> SumkinResult<()> {
+        debug!("CHECKPOINT SQL: {}", sql::CHECKPOINT_SQL);
+        let row = sqlx::query(sql::CHECKPOINT_SQL).fetch_one(&self.pool).await?;
+        let busy: i64 = row.try_get(0)?;
+        if busy != 0 {
+            return Err(Error::CheckpointBusy);
+        }
+        let checkpointed: i64 = row.try_get(2)?;
+        debug!("Checkpointed {} WAL frames", checkpointed);
+        Ok(())
+    }
+
+    /// Fraction of the database file's pages that are free, from `PRAGMA freelist_count` over
+    /// `PRAGMA page_count`. Free pages are only returned to the filesystem by `VACUUM`, so a
+    /// high ratio after pruning suggests running one.
+    pub async fn free_page_ratio(&self) -> SumkinResult<f64> {
+        let mut conn = self.pool.acquire().await?;
+        debug!("FREELIST COUNT SQL: {}", sql::FREELIST_COUNT_SQL);
+        let free: i64 = sqlx::query(sql::FREELIST_COUNT_SQL).fetch_one(&mut conn).await?.try_get(0)?;
+        debug!("PAGE COUNT SQL: {}", sql::PAGE_COUNT_SQL);
+        let pages: i64 = sqlx::query(sql::PAGE_COUNT_SQL).fetch_one(&mut conn).await?.try_get(0)?;
+        if pages == 0 {
+            return Ok(0.0);
+        }
+        Ok(free as f64 / pages as f64)
+    }
+
+    /// Runs `EXPLAIN QUERY PLAN` for `list_current(prefix, ..)` and returns the plan lines,
+    /// to check which indexes the list query hits.
+    pub async fn explain(&self, prefix: &str) -> SumkinResult<Vec<String>> {
+        let sql = format!("EXPLAIN QUERY PLAN {}", sql::GET_CURRENT_SQL.as_str());
+        debug!("EXPLAIN SQL: {}", &sql);
+        let rows = sqlx::query(&sql)
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(false)
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok(row.try_get("detail")?))
+            .collect()
+    }
+
+    /// Closes the pool, waiting for connections in use to be returned. Every call afterwards
+    /// fails with `Error::Closed`, as do calls on clones of this backend.
+    pub async fn close(&self) {
+        if let Some(slots) = &self.write_slots {
+            slots.permits.close();
+        }
+        self.pool.close().await;
+    }
+
+    /// Waits for a write slot if `max_concurrent_writes` is set. The write keeps the returned
+    /// permit until its transaction is done. Fails with `Error::WritesHeld` instead of waiting
+    /// when open `begin` transactions hold every slot, as they may never be released.
+    async fn write_permit(&self) -> SumkinResult<Option<OwnedSemaphorePermit>> {
+        let slots = match &self.write_slots {
+            Some(slots) => slots,
+            None => return Ok(None)
+        };
+        match slots.permits.clone().try_acquire_owned() {
+            Ok(permit) => return Ok(Some(permit)),
+            Err(TryAcquireError::Closed) => return Err(Error::Closed),
+            Err(TryAcquireError::NoPermits) => {}
+        }
+        let held = slots.held_by_transactions.load(Ordering::SeqCst);
+        if held >= slots.limit {
+            return Err(Error::WritesHeld { held });
+        }
+        Ok(Some(slots.permits.clone().acquire_owned().await.map_err(|_| Error::Closed)?))
+    }
+
+    /// Replaces the clock used to timestamp writes, e.g. with a `MockClock` in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Registers `callback` to be called after each committed put or delete, once per key
+    /// written, replacing any previous callback. It runs on the writing task after the
+    /// transaction is released, so it should hand work off rather than block.
+    pub fn on_change(mut self, callback: ChangeCallback) -> Self {
+        self.on_change = Some(ChangeHook(callback));
+        self
+    }
+
+    fn notify(&self, op: ChangeOp, name: &str, revision: Revision) {
+        if let Some(ChangeHook(callback)) = &self.on_change {
+            callback(ChangeEvent::new(op, name, revision));
+        }
+    }
+
+    async fn get_with_tx(tx: &mut Transaction<'_, Sqlite>, name: &str, revision: Option<Revision>) -> SumkinResult<Option<KeyValue>> {
+        if let Some(_r) = revision {
+            unimplemented!();
+        } else {
+            let kv = Self::list_current_with_tx(tx, &normalize_prefix(name), 1, false).await?;
+            Ok(kv.into_iter().next())
+        }
+
+    }
+
+    async fn list_current_with_tx(tx: &mut Transaction<'_, Sqlite>, prefix: &PrefixMatch, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
+        Self::list_like_with_tx(tx, &prefix.like_pattern(), limit, include_deleted).await
+    }
+
+    async fn list_like_with_tx(tx: &mut Transaction<'_, Sqlite>, pattern: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
+        let sql = if limit > 0 {
+            format!("{} LIMIT {}", sql::GET_CURRENT_SQL.as_str(), limit)
+        } else {
+            sql::GET_CURRENT_SQL.clone()
+        };
+
+        debug!("LIST SQL: {}", &sql);
+
+        let rows = sqlx::query_as::<_, KeyValue>(&sql)
+            .bind(pattern)
+            .bind(include_deleted)
+            .fetch_all(tx).await?;
+        Ok(rows)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn insert_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, name: &str, created: bool, deleted: bool, create_revision: Revision, prev_revision: Option<Revision>, lease: Option<i64>, value: Option<&[u8]>, old_value: Option<Vec<u8>>, tag: Option<&str>, content_type: Option<&str>) -> SumkinResult<Revision> {
+        let (value, value_ref) = self.store_blob_with_tx(tx, value).await?;
+        let (old_value, old_value_ref) = self.store_blob_with_tx(tx, old_value.as_deref()).await?;
+        let id = self.next_revision_with_tx(tx).await?;
+        debug!("INSERT SQL: {}", sql::INSERT);
+        let row = sqlx::query(sql::INSERT)
+            .bind(id)
+            .bind(name)
+            .bind(created)
+            .bind(deleted)
+            .bind(create_revision)
+            .bind(prev_revision)
+            .bind(lease)
+            .bind(value)
+            .bind(old_value)
+            .bind(tag)
+            .bind(self.clock.now_millis())
+            .bind(content_type)
+            .bind(value_ref)
+            .bind(old_value_ref)
+            .execute(tx).await?;
+        let revision = row.last_insert_rowid().into();
+        self.check_revision(revision)?;
+        Ok(revision)
+    }
+
+    /// Enforces `revision_warn_threshold` and `max_revision` on a revision being written.
+    fn check_revision(&self, revision: Revision) -> SumkinResult<()> {
+        if let Some(max_revision) = self.config.max_revision.filter(|max| revision > *max) {
+            return Err(Error::RevisionLimit { revision, max_revision });
+        }
+        if let Some(threshold) = self.config.revision_warn_threshold.filter(|threshold| revision >= *threshold) {
+            warn!("Revision {} is past the warning threshold of {}", revision, threshold);
+        }
+        Ok(())
+    }
+
+    /// The revision the next insert must use in `revision_seq` mode, or `None` to let
+    /// AUTOINCREMENT assign one.
+    async fn next_revision_with_tx(&self, tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<Option<Revision>> {
+        if !self.config.revision_seq {
+            return Ok(None);
+        }
+        debug!("NEXT REVISION SQL: {}", sql::NEXT_REVISION_SQL);
+        sqlx::query(sql::NEXT_REVISION_SQL).execute(&mut *tx).await?;
+        debug!("REVISION SEQ SQL: {}", sql::REVISION_SEQ_SQL);
+        let revision: Revision = sqlx::query(sql::REVISION_SEQ_SQL).fetch_one(tx).await?.try_get(0)?;
+        Ok(Some(revision))
+    }
+
+    /// Moves `value` into `sumkin_blobs` if it's over the configured `blob_threshold`,
+    /// returning what to store inline and the blob reference.
+    async fn store_blob_with_tx<'v>(&self, tx: &mut Transaction<'_, Sqlite>, value: Option<&'v [u8]>) -> SumkinResult<(Option<&'v [u8]>, Option<String>)> {
+        match (value, self.config.blob_threshold) {
+            (Some(value), Some(threshold)) if value.len() > threshold => {
+                let hash = blob_hash(value);
+                debug!("INSERT BLOB SQL: {}", sql::INSERT_BLOB_SQL);
+                sqlx::query(sql::INSERT_BLOB_SQL).bind(&hash).bind(value).execute(tx).await?;
+                Ok((None, Some(hash)))
+            }
+            _ => Ok((value, None))
+        }
+    }
+
+
+    async fn put_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, name: &str, value: &[u8], tag: Option<&str>, content_type: Option<&str>) -> SumkinResult<Revision> {
+        let revision = if let Some(kv) = Self::get_with_tx(tx, name, None).await? {
+            self.update_with_tx(tx, &kv, value, tag, content_type).await?
+        } else {
+            debug!("Creating new key: {}", name);
+            let revision = self.insert_with_tx(tx, name, true, false, Revision::default(), None, None, Some(value), None, tag, content_type).await?;
+            Self::set_create_revision_with_tx(tx, revision).await?;
+            self.retain_with_tx(tx, name).await?;
+            revision
+        };
+        Ok(revision)
+    }
+
+    async fn set_create_revision_with_tx(tx: &mut Transaction<'_, Sqlite>, revision: Revision) -> SumkinResult<()> {
+        debug!("SET CREATE REVISION SQL: {}", sql::SET_CREATE_REVISION_SQL);
+        sqlx::query(sql::SET_CREATE_REVISION_SQL).bind(revision).execute(tx).await?;
+        Ok(())
+    }
+
+    /// Writes `value` as the next revision of the existing key `kv`, keeping its create_revision.
+    async fn update_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, kv: &KeyValue, value: &[u8], tag: Option<&str>, content_type: Option<&str>) -> SumkinResult<Revision> {
+        debug!("Updating existing key: {}", kv.key());
+        let revision = self.insert_with_tx(tx, kv.key(), false, false, *kv.create_revision(), Some(*kv.mod_revision()), *kv.lease(), Some(value), kv.value().clone(), tag, content_type).await?;
+        self.retain_with_tx(tx, kv.key()).await?;
+        Ok(revision)
+    }
+
+    /// Enforces `per_key_retention` for `name`, if configured.
+    async fn retain_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, name: &str) -> SumkinResult<()> {
+        if let Some(retention) = self.config.per_key_retention {
+            debug!("RETAIN SQL: {}", sql::RETAIN_SQL);
+            sqlx::query(sql::RETAIN_SQL)
+                .bind(name)
+                .bind(name)
+                .bind(i64::from(retention) + 1)
+                .execute(tx).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes a delete for the existing key `kv`, keeping its value as `old_value`, or in
+    /// `hard_delete` mode removes all of its rows and returns a revision no row will use.
+    async fn tombstone_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, kv: &KeyValue) -> SumkinResult<Revision> {
+        if self.config.hard_delete {
+            debug!("HARD DELETE SQL: {}", sql::HARD_DELETE_SQL);
+            sqlx::query(sql::HARD_DELETE_SQL).bind(kv.key()).execute(&mut *tx).await?;
+            return self.skip_revision_with_tx(tx).await;
+        }
+        debug!("Deleting key: {}", kv.key());
+        self.insert_with_tx(tx, kv.key(), false, true, Revision::default(), Some(*kv.mod_revision()), None, None, kv.value().clone(), None, None).await
+    }
+
+    /// Consumes the revision the next insert would have taken, without writing a row.
+    async fn skip_revision_with_tx(&self, tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<Revision> {
+        if let Some(revision) = self.next_revision_with_tx(tx).await? {
+            return Ok(revision);
+        }
+        debug!("SKIP REVISION SQL: {}", sql::SKIP_REVISION_SQL);
+        sqlx::query(sql::SKIP_REVISION_SQL).execute(&mut *tx).await?;
+        debug!("SEQUENCE SQL: {}", sql::SEQUENCE_SQL);
+        let revision: Revision = sqlx::query(sql::SEQUENCE_SQL).fetch_one(tx).await?.try_get(0)?;
+        Ok(revision)
+    }
+
+    async fn bump_generation_with_tx(tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<()> {
+        debug!("BUMP GENERATION SQL: {}", sql::BUMP_GENERATION_SQL);
+        sqlx::query(sql::BUMP_GENERATION_SQL).execute(tx).await?;
+        Ok(())
+    }
+
+    /// Deletes out-of-line blobs and annotations that no row refers to any more.
+    async fn prune_unreferenced_with_tx(tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<()> {
+        debug!("PRUNE BLOBS SQL: {}", sql::PRUNE_BLOBS_SQL);
+        let blobs = sqlx::query(sql::PRUNE_BLOBS_SQL).execute(&mut *tx).await?.rows_affected();
+        if blobs > 0 {
+            info!("Pruned {} unreferenced blobs", blobs);
+        }
+        debug!("PRUNE ANNOTATIONS SQL: {}", sql::PRUNE_ANNOTATIONS_SQL);
+        sqlx::query(sql::PRUNE_ANNOTATIONS_SQL).execute(tx).await?;
+        Ok(())
+    }
+
+    async fn grant_lease_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, ttl_seconds: i64) -> SumkinResult<i64> {
+        debug!("GRANT LEASE SQL: {}", sql::GRANT_LEASE_SQL);
+        let expires_at = self.clock.now_millis() + ttl_seconds * 1000;
+        let lease = sqlx::query(sql::GRANT_LEASE_SQL)
+            .bind(ttl_seconds)
+            .bind(expires_at)
+            .execute(tx).await?;
+        Ok(lease.last_insert_rowid())
+    }
+
+    async fn set_compact_revision_with_tx(&self, tx: &mut Transaction<'_, Sqlite>, revision: Revision) -> SumkinResult<()> {
+        debug!("UPDATE COMPACT REV SQL: {}", sql::UPDATE_COMPACT_REV_SQL);
+        let updated = sqlx::query(sql::UPDATE_COMPACT_REV_SQL).bind(revision).execute(&mut *tx).await?;
+        if updated.rows_affected() == 0 {
+            debug!("Creating compaction marker");
+            self.insert_with_tx(tx, "compact_rev_key", true, false, Revision::default(), Some(revision), None, None, None, None, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn current_revision_with_tx(tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<Revision> {
+        debug!("CURRENT REVISION SQL: {}", sql::CURRENT_REVISION_SQL);
+        let revision: Revision = sqlx::query(sql::CURRENT_REVISION_SQL).fetch_one(tx).await?.try_get("id")?;
+        Ok(revision)
+    }
+}
+
+#[async_trait]
+impl Backend for SqliteBackend {
+    async fn size(&self) -> SumkinResult<u64> {
+        debug!("SIZE SQL: {}", sql::SIZE_SQL);
+        let size: i64 = sqlx::query(sql::SIZE_SQL).fetch_one(&self.pool).await?.try_get(0)?;
+        Ok(size as u64)
+    }
+
+    async fn compact_revision(&self) -> SumkinResult<Revision> {
+        debug!("COMPACT REV SQL: {}", sql::COMPACT_REV_SQL);
+        let revision: Option<Revision> = sqlx::query(sql::COMPACT_REV_SQL).fetch_one(&self.pool).await?.try_get("prev_revision")?;
+        Ok(revision.unwrap_or_default())
+    }
+
+    async fn current_revision(&self) -> SumkinResult<Revision> {
+        debug!("CURRENT REVISION SQL: {}", sql::CURRENT_REVISION_SQL);
+        let revision: Revision = sqlx::query(sql::CURRENT_REVISION_SQL).fetch_one(&self.pool).await?.try_get("id")?;
+        Ok(revision)
+    }
+
+    async fn count(&self, prefix: &str) -> SumkinResult<u64> {
+        debug!("COUNT SQL: {}", sql::COUNT_SQL.as_str());
+        let row = sqlx::query(sql::COUNT_SQL.as_str()).bind(self.config.prefix_match(prefix).like_pattern()).fetch_one(&self.pool).await?;
+        let count: i64 = row.try_get("count")?;
+        Ok(count as u64)
+    }
+
+    async fn log_count(&self) -> SumkinResult<u64> {
+        debug!("LOG COUNT SQL: {}", sql::LOG_COUNT_SQL);
+        let count: i64 = sqlx::query(sql::LOG_COUNT_SQL).fetch_one(&self.pool).await?.try_get(0)?;
+        Ok(count as u64)
+    }
+
+    async fn write_stats(&self) -> SumkinResult<WriteStats> {
+        debug!("WRITE STATS SQL: {}", sql::WRITE_STATS_SQL);
+        let stats = sqlx::query_as::<_, WriteStats>(sql::WRITE_STATS_SQL).fetch_one(&self.pool).await?;
+        Ok(stats)
+    }
+
+    async fn is_empty(&self) -> SumkinResult<bool> {
+        debug!("IS EMPTY SQL: {}", sql::IS_EMPTY_SQL);
+        let empty: bool = sqlx::query(sql::IS_EMPTY_SQL).fetch_one(&self.pool).await?.try_get(0)?;
+        Ok(empty)
+    }
+
+    async fn prefix_exists(&self, prefix: &str) -> SumkinResult<bool> {
+        debug!("PREFIX EXISTS SQL: {}", sql::PREFIX_EXISTS_SQL);
+        let exists: bool = sqlx::query(sql::PREFIX_EXISTS_SQL).bind(self.config.prefix_match(prefix).like_pattern()).fetch_one(&self.pool).await?.try_get(0)?;
+        Ok(exists)
+    }
+
+    async fn count_by_top_level(&self) -> SumkinResult<Vec<(String, u64)>> {
+        debug!("COUNT BY TOP LEVEL SQL: {}", sql::COUNT_BY_TOP_LEVEL_SQL);
+        let rows = sqlx::query(sql::COUNT_BY_TOP_LEVEL_SQL).fetch_all(&self.pool).await?;
+        let mut counts = Vec::with_capacity(rows.len());
+        for row in rows {
+            let count: i64 = row.try_get("count")?;
+            counts.push((row.try_get("segment")?, count as u64));
+        }
+        Ok(counts)
+    }
+
+    async fn count_all(&self, prefix: &str) -> SumkinResult<(u64, u64)> {
+        debug!("COUNT ALL SQL: {}", sql::COUNT_ALL_SQL);
+        let pattern = self.config.prefix_match(prefix).like_pattern();
+        let row = sqlx::query(sql::COUNT_ALL_SQL).bind(&pattern).fetch_one(&self.pool).await?;
+        let live: i64 = row.try_get("live")?;
+        let tombstoned: i64 = row.try_get("tombstoned")?;
+        Ok((live as u64, tombstoned as u64))
+    }
+
+    async fn get_first(&self, names: &[&str]) -> SumkinResult<Option<(String, KeyValue)>> {
+        let mut tx = self.pool.begin().await?;
+        let mut found = None;
+        for name in names {
+            if let Some(kv) = Self::get_with_tx(&mut tx, name, None).await? {
+                found = Some((name.to_string(), kv));
+                break;
+            }
+        }
+        tx.commit().await?;
+        Ok(found)
+    }
+
+    async fn get_map(&self, names: &[&str]) -> SumkinResult<HashMap<String, KeyValue>> {
+        let mut map = HashMap::with_capacity(names.len());
+        let mut tx = self.pool.begin().await?;
+        for chunk in names.chunks(GET_MAP_CHUNK_SIZE) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = sql::GET_MAP_SQL.replace("{}", &placeholders);
+            debug!("GET MAP SQL: {}", &sql);
+            let mut query = sqlx::query_as::<_, KeyValue>(&sql);
+            for name in chunk {
+                query = query.bind(*name);
+            }
+            for kv in query.fetch_all(&mut tx).await? {
+                map.insert(kv.key().clone(), kv);
+            }
+        }
+        tx.commit().await?;
+        Ok(map)
+    }
+
+    async fn stat(&self, name: &str) -> SumkinResult<Option<KeyStat>> {
+        debug!("STAT SQL: {}", sql::STAT_SQL);
+        let stat = sqlx::query_as::<_, KeyStat>(sql::STAT_SQL)
+            .bind(name)
+            .fetch_optional(&self.pool).await?;
+        Ok(stat)
+    }
+
+    async fn put(&self, name: &str, value: &[u8]) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let revision = self.put_with_tx(&mut tx, name, value, None, None).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn grant_lease(&self, ttl_seconds: i64) -> SumkinResult<i64> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let lease_id = self.grant_lease_with_tx(&mut tx, ttl_seconds).await?;
+        tx.commit().await?;
+        Ok(lease_id)
+    }
+
+    async fn keep_alive(&self, lease_id: i64) -> SumkinResult<()> {
+        let _permit = self.write_permit().await?;
+        debug!("KEEP ALIVE SQL: {}", sql::KEEP_ALIVE_SQL);
+        let updated = sqlx::query(sql::KEEP_ALIVE_SQL)
+            .bind(self.clock.now_millis())
+            .bind(lease_id)
+            .execute(&self.pool).await?;
+        if updated.rows_affected() == 0 {
+            return Err(Error::LeaseNotFound { id: lease_id });
+        }
+        Ok(())
+    }
+
+    async fn active_leases(&self) -> SumkinResult<Vec<i64>> {
+        debug!("ACTIVE LEASES SQL: {}", sql::ACTIVE_LEASES_SQL);
+        let leases = sqlx::query_scalar(sql::ACTIVE_LEASES_SQL).fetch_all(&self.pool).await?;
+        Ok(leases)
+    }
+
+    async fn attach_lease(&self, name: &str, lease_id: Option<i64>) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let kv = Self::get_with_tx(&mut tx, name, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+        if let Some(id) = lease_id {
+            debug!("LEASE EXISTS SQL: {}", sql::LEASE_EXISTS_SQL);
+            let exists: bool = sqlx::query(sql::LEASE_EXISTS_SQL).bind(id).fetch_one(&mut tx).await?.try_get(0)?;
+            if !exists {
+                return Err(Error::LeaseNotFound { id });
+            }
+        }
+        let revision = self.insert_with_tx(&mut tx, name, false, false, *kv.create_revision(), Some(*kv.mod_revision()), lease_id, kv.value().as_deref(), kv.value().clone(), kv.tag().as_deref(), kv.content_type().as_deref()).await?;
+        self.retain_with_tx(&mut tx, name).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn touch(&self, name: &str) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let kv = Self::get_with_tx(&mut tx, name, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+        debug!("Touching key: {}", name);
+        let revision = self.insert_with_tx(&mut tx, name, false, false, *kv.create_revision(), Some(*kv.mod_revision()), *kv.lease(), kv.value().as_deref(), kv.value().clone(), kv.tag().as_deref(), kv.content_type().as_deref()).await?;
+        self.retain_with_tx(&mut tx, name).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn create_or_get(&self, name: &str, value: &[u8]) -> SumkinResult<(bool, KeyValue)> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let id = self.next_revision_with_tx(&mut tx).await?;
+        let (value, value_ref) = self.store_blob_with_tx(&mut tx, Some(value)).await?;
+        debug!("CREATE IF ABSENT SQL: {}", sql::CREATE_IF_ABSENT_SQL);
+        let created = sqlx::query(sql::CREATE_IF_ABSENT_SQL)
+            .bind(id)
+            .bind(name)
+            .bind(None::<i64>)
+            .bind(value)
+            .bind(self.clock.now_millis())
+            .bind(value_ref)
+            .bind(name)
+            .execute(&mut tx).await?;
+        if created.rows_affected() == 0 {
+            let kv = Self::get_with_tx(&mut tx, name, None).await?
+                .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+            // Nothing to keep: this undoes the revision_seq bump and the blob, if any.
+            tx.rollback().await?;
+            return Ok((false, kv));
+        }
+        let revision = created.last_insert_rowid().into();
+        self.check_revision(revision)?;
+        Self::set_create_revision_with_tx(&mut tx, revision).await?;
+        self.retain_with_tx(&mut tx, name).await?;
+        let kv = Self::get_with_tx(&mut tx, name, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok((true, kv))
+    }
+
+    async fn put_batch(&self, entries: &[(&str, &[u8])], coalesce: bool) -> SumkinResult<Vec<Revision>> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let mut revisions = Vec::with_capacity(entries.len());
+        let mut i = 0;
+        while i < entries.len() {
+            let (name, _) = entries[i];
+            let mut run = 1;
+            if coalesce {
+                while i + run < entries.len() && entries[i + run].0 == name {
+                    run += 1;
+                }
+            }
+            let (_, value) = entries[i + run - 1];
+            let revision = self.put_with_tx(&mut tx, name, value, None, None).await?;
+            revisions.extend(std::iter::repeat_n(revision, run));
+            i += run;
+        }
+        tx.commit().await?;
+        for (i, ((name, _), revision)) in entries.iter().zip(&revisions).enumerate() {
+            if i == 0 || revisions[i - 1] != *revision {
+                self.notify(ChangeOp::Put, name, *revision);
+            }
+        }
+        Ok(revisions)
+    }
+
+    async fn bootstrap_if_empty(&self, prefix: &str, entries: &[(&str, &[u8])]) -> SumkinResult<bool> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        debug!("PREFIX EXISTS SQL: {}", sql::PREFIX_EXISTS_SQL);
+        let exists: bool = sqlx::query(sql::PREFIX_EXISTS_SQL).bind(self.config.prefix_match(prefix).like_pattern()).fetch_one(&mut tx).await?.try_get(0)?;
+        if exists {
+            tx.rollback().await?;
+            return Ok(false);
+        }
+        let mut revisions = Vec::with_capacity(entries.len());
+        for (name, value) in entries {
+            revisions.push(self.put_with_tx(&mut tx, name, value, None, None).await?);
+        }
+        tx.commit().await?;
+        for ((name, _), revision) in entries.iter().zip(&revisions) {
+            self.notify(ChangeOp::Put, name, *revision);
+        }
+        Ok(true)
+    }
+
+    async fn put_batch_update(&self, entries: &[(&str, &[u8])]) -> SumkinResult<Vec<Revision>> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let mut revisions = Vec::with_capacity(entries.len());
+        for (name, value) in entries {
+            let kv = Self::get_with_tx(&mut tx, name, None).await?
+                .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+            revisions.push(self.update_with_tx(&mut tx, &kv, value, None, None).await?);
+        }
+        tx.commit().await?;
+        for ((name, _), revision) in entries.iter().zip(&revisions) {
+            self.notify(ChangeOp::Put, name, *revision);
+        }
+        Ok(revisions)
+    }
+
+    async fn swap(&self, a: &str, b: &str) -> SumkinResult<(Revision, Revision)> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let kv_a = Self::get_with_tx(&mut tx, a, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: a.to_string() })?;
+        let kv_b = Self::get_with_tx(&mut tx, b, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: b.to_string() })?;
+        let revision_a = self.update_with_tx(&mut tx, &kv_a, &kv_b.value().clone().unwrap_or_default(), None, None).await?;
+        // Re-read `b`, which is `a` again when swapping a key with itself.
+        let kv_b = Self::get_with_tx(&mut tx, b, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: b.to_string() })?;
+        let revision_b = self.update_with_tx(&mut tx, &kv_b, &kv_a.value().clone().unwrap_or_default(), None, None).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, a, revision_a);
+        self.notify(ChangeOp::Put, b, revision_b);
+        Ok((revision_a, revision_b))
+    }
+
+    async fn put_idempotent(&self, name: &str, value: &[u8], request_id: &str) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        debug!("GET REQUEST SQL: {}", sql::GET_REQUEST_SQL);
+        let seen: Option<Revision> = sqlx::query_scalar(sql::GET_REQUEST_SQL).bind(request_id).fetch_optional(&mut tx).await?;
+        if let Some(revision) = seen {
+            debug!("Request {} already applied at revision {}", request_id, revision);
+            return Ok(revision);
+        }
+        let revision = self.put_with_tx(&mut tx, name, value, None, None).await?;
+        debug!("INSERT REQUEST SQL: {}", sql::INSERT_REQUEST_SQL);
+        sqlx::query(sql::INSERT_REQUEST_SQL).bind(request_id).bind(revision).execute(&mut tx).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn append(&self, name: &str, data: &[u8]) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let revision = match Self::get_with_tx(&mut tx, name, None).await? {
+            Some(kv) => {
+                let mut value = kv.value().clone().unwrap_or_default();
+                value.extend_from_slice(data);
+                self.update_with_tx(&mut tx, &kv, &value, None, None).await?
+            }
+            None => self.put_with_tx(&mut tx, name, data, None, None).await?
+        };
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn revert(&self, name: &str, to_revision: Revision) -> SumkinResult<Revision> {
+        self.ensure_not_compacted(to_revision).await?;
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        debug!("GET AS OF SQL: {}", sql::GET_AS_OF_SQL.as_str());
+        let kv = sqlx::query_as::<_, KeyValue>(sql::GET_AS_OF_SQL.as_str())
+            .bind(name)
+            .bind(to_revision)
+            .fetch_optional(&mut tx).await?
+            .filter(|kv| !*kv.deleted())
+            .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+        let revision = self.put_with_tx(&mut tx, name, &kv.value().clone().unwrap_or_default(), None, None).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn put_tagged(&self, name: &str, tag: &str, value: &[u8]) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let revision = self.put_with_tx(&mut tx, name, value, Some(tag), None).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn modified_between(&self, prefix: &str, start_ms: i64, end_ms: i64) -> SumkinResult<Vec<KeyValue>> {
+        debug!("MODIFIED BETWEEN SQL: {}", sql::MODIFIED_BETWEEN_SQL.as_str());
+        let pattern = self.config.prefix_match(prefix).like_pattern();
+        let rows = sqlx::query_as::<_, KeyValue>(sql::MODIFIED_BETWEEN_SQL.as_str())
+            .bind(&pattern)
+            .bind(start_ms)
+            .bind(end_ms)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn created_since(&self, prefix: &str, revision: Revision) -> SumkinResult<Vec<KeyValue>> {
+        debug!("CREATED SINCE SQL: {}", sql::CREATED_SINCE_SQL.as_str());
+        let rows = sqlx::query_as::<_, KeyValue>(sql::CREATED_SINCE_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(revision)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn list_stale(&self, prefix: &str, older_than_ms: i64) -> SumkinResult<Vec<KeyValue>> {
+        debug!("LIST STALE SQL: {}", sql::LIST_STALE_SQL.as_str());
+        let cutoff = self.clock.now_millis() - older_than_ms;
+        let rows = sqlx::query_as::<_, KeyValue>(sql::LIST_STALE_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(cutoff)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn list_numeric_sorted(&self, prefix: &str, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        let mut sql = sql::LIST_NUMERIC_SQL.clone();
+        let limit = self.config.list_limit(limit);
+        if limit > 0 {
+            sql = format!("{} LIMIT {}", sql, limit);
+        }
+        debug!("LIST NUMERIC SQL: {}", &sql);
+        let rows = sqlx::query_as::<_, KeyValue>(&sql)
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(false)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn list_prefixes(&self, prefixes: &[&str], limit_per: i64) -> SumkinResult<HashMap<String, Vec<KeyValue>>> {
+        let limit = self.config.list_limit(limit_per);
+        let mut tx = self.pool.begin().await?;
+        let mut listings = HashMap::with_capacity(prefixes.len());
+        for prefix in prefixes {
+            let kvs = Self::list_current_with_tx(&mut tx, &self.config.prefix_match(prefix), limit, false).await?;
+            listings.insert(prefix.to_string(), kvs);
+        }
+        tx.commit().await?;
+        Ok(listings)
+    }
+
+    async fn list_shard(&self, prefix: &str, shard: u32, total_shards: u32) -> SumkinResult<Vec<KeyValue>> {
+        if total_shards == 0 {
+            return Err(Error::InvalidArgument { reason: "total_shards must be at least 1".to_string() });
+        }
+        let mut tx = self.pool.begin().await?;
+        // Shards are picked by name alone, so only the shard's own rows are read with values.
+        debug!("REVISION MAP SQL: {}", sql::REVISION_MAP_SQL.as_str());
+        let rows = sqlx::query(sql::REVISION_MAP_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .fetch_all(&mut tx).await?;
+        let mut ids = Vec::new();
+        for row in &rows {
+            let name: String = row.try_get("name")?;
+            if shard_of(&name, total_shards) == shard {
+                ids.push(row.try_get::<i64, _>("id")?);
+            }
+        }
+        ids.sort_unstable();
+        let mut kvs = Vec::with_capacity(ids.len());
+        for chunk in ids.chunks(GET_MAP_CHUNK_SIZE) {
+            let placeholders = vec!["?"; chunk.len()].join(", ");
+            let sql = sql::GET_REVISIONS_SQL.replace("{}", &placeholders);
+            debug!("GET REVISIONS SQL: {}", &sql);
+            let mut query = sqlx::query_as::<_, KeyValue>(&sql);
+            for id in chunk {
+                query = query.bind(*id);
+            }
+            kvs.extend(query.fetch_all(&mut tx).await?);
+        }
+        tx.commit().await?;
+        Ok(kvs)
+    }
+
+    async fn list_matching(&self, pattern: &str, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        let mut tx = self.pool.begin().await?;
+        let kvs = Self::list_like_with_tx(&mut tx, pattern, self.config.list_limit(limit), false).await?;
+        tx.commit().await?;
+        Ok(kvs)
+    }
+
+    async fn range_keys(&self, key: &[u8], range_end: &[u8], limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        let (start, end) = range_bounds(key, range_end)?;
+        let mut sql = sql::RANGE_SQL.replace("{}", range_end_predicate(&end));
+        let limit = self.config.list_limit(limit);
+        if limit > 0 {
+            sql = format!("{} LIMIT {}", sql, limit);
+        }
+        debug!("RANGE SQL: {}", &sql);
+
+        let mut query = sqlx::query_as::<_, KeyValue>(&sql).bind(start);
+        if let Some(end) = &end {
+            query = query.bind(end);
+        }
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+
+    async fn floor(&self, key: &str) -> SumkinResult<Option<KeyValue>> {
+        debug!("FLOOR SQL: {}", sql::FLOOR_SQL.as_str());
+        let kv = sqlx::query_as::<_, KeyValue>(sql::FLOOR_SQL.as_str())
+            .bind(key)
+            .fetch_optional(&self.pool).await?;
+        Ok(kv)
+    }
+
+    async fn ceiling(&self, key: &str) -> SumkinResult<Option<KeyValue>> {
+        debug!("CEILING SQL: {}", sql::CEILING_SQL.as_str());
+        let kv = sqlx::query_as::<_, KeyValue>(sql::CEILING_SQL.as_str())
+            .bind(key)
+            .fetch_optional(&self.pool).await?;
+        Ok(kv)
+    }
+
+    async fn count_range(&self, key: &[u8], range_end: &[u8]) -> SumkinResult<u64> {
+        let (start, end) = range_bounds(key, range_end)?;
+        let sql = sql::COUNT_RANGE_SQL.replace("{}", range_end_predicate(&end));
+        debug!("COUNT RANGE SQL: {}", &sql);
+
+        let mut query = sqlx::query(&sql).bind(start);
+        if let Some(end) = &end {
+            query = query.bind(end);
+        }
+        let count: i64 = query.fetch_one(&self.pool).await?.try_get("count")?;
+        Ok(count as u64)
+    }
+
+    async fn set_annotation(&self, name: &str, label: &str, value: &str) -> SumkinResult<()> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let kv = Self::get_with_tx(&mut tx, name, None).await?
+            .ok_or_else(|| Error::KeyNotFound { name: name.to_string() })?;
+        debug!("SET ANNOTATION SQL: {}", sql::SET_ANNOTATION_SQL);
+        sqlx::query(sql::SET_ANNOTATION_SQL)
+            .bind(name)
+            .bind(kv.mod_revision())
+            .bind(label)
+            .bind(value)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_annotations(&self, name: &str) -> SumkinResult<HashMap<String, String>> {
+        debug!("GET ANNOTATIONS SQL: {}", sql::GET_ANNOTATIONS_SQL);
+        let rows = sqlx::query(sql::GET_ANNOTATIONS_SQL).bind(name).fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get("label")?, row.try_get("value")?)))
+            .collect()
+    }
+
+    async fn list_annotated(&self, label: &str, value: &str) -> SumkinResult<Vec<KeyValue>> {
+        debug!("LIST ANNOTATED SQL: {}", sql::LIST_ANNOTATED_SQL.as_str());
+        let kvs = sqlx::query_as::<_, KeyValue>(sql::LIST_ANNOTATED_SQL.as_str())
+            .bind(label)
+            .bind(value)
+            .fetch_all(&self.pool).await?;
+        Ok(kvs)
+    }
+
+    async fn revision_map(&self, prefix: &str) -> SumkinResult<BTreeMap<String, Revision>> {
+        debug!("REVISION MAP SQL: {}", sql::REVISION_MAP_SQL.as_str());
+        let rows = sqlx::query(sql::REVISION_MAP_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok((row.try_get("name")?, row.try_get("id")?)))
+            .collect()
+    }
+
+    async fn list_hashes(&self, prefix: &str) -> SumkinResult<Vec<(String, Revision, u32)>> {
+        debug!("LIST HASHES SQL: {}", sql::LIST_HASHES_SQL);
+        let mut rows = sqlx::query(sql::LIST_HASHES_SQL)
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .fetch(&self.pool);
+        let mut hashes = Vec::new();
+        while let Some(row) = rows.try_next().await? {
+            let value: Option<Vec<u8>> = row.try_get("value")?;
+            hashes.push((row.try_get("name")?, row.try_get("id")?, VALUE_CRC.checksum(value.as_deref().unwrap_or_default())));
+        }
+        Ok(hashes)
+    }
+
+    async fn value_size_histogram(&self, prefix: &str, buckets: &[i64]) -> SumkinResult<Vec<u64>> {
+        if buckets.is_empty() {
+            return Ok(vec![self.count(prefix).await?]);
+        }
+        let cases = (0..buckets.len()).map(|i| format!("WHEN size < ? THEN {}", i)).collect::<Vec<_>>().join(" ");
+        let sql = sql::VALUE_SIZE_HISTOGRAM_SQL.replace("{}", &cases);
+        debug!("VALUE SIZE HISTOGRAM SQL: {}", &sql);
+        let mut query = sqlx::query(&sql);
+        for bound in buckets {
+            query = query.bind(bound);
+        }
+        let rows = query
+            .bind(buckets.len() as i64)
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .fetch_all(&self.pool).await?;
+        let mut counts = vec![0; buckets.len() + 1];
+        for row in rows {
+            let bucket: i64 = row.try_get("bucket")?;
+            let count: i64 = row.try_get("count")?;
+            counts[bucket as usize] = count as u64;
+        }
+        Ok(counts)
+    }
+
+    async fn list_empty_values(&self, prefix: &str) -> SumkinResult<Vec<KeyValue>> {
+        debug!("LIST EMPTY VALUES SQL: {}", sql::LIST_EMPTY_VALUES_SQL.as_str());
+        let pattern = self.config.prefix_match(prefix).like_pattern();
+        let rows = sqlx::query_as::<_, KeyValue>(sql::LIST_EMPTY_VALUES_SQL.as_str())
+            .bind(&pattern)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn put_with_meta(&self, name: &str, value: &[u8], content_type: &str) -> SumkinResult<Revision> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let revision = self.put_with_tx(&mut tx, name, value, None, Some(content_type)).await?;
+        tx.commit().await?;
+        self.notify(ChangeOp::Put, name, revision);
+        Ok(revision)
+    }
+
+    async fn list_by_content_type(&self, content_type: &str) -> SumkinResult<Vec<KeyValue>> {
+        debug!("LIST BY CONTENT TYPE SQL: {}", sql::LIST_BY_CONTENT_TYPE_SQL.as_str());
+        let rows = sqlx::query_as::<_, KeyValue>(sql::LIST_BY_CONTENT_TYPE_SQL.as_str())
+            .bind(content_type)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn list_by_tag(&self, tag: &str) -> SumkinResult<Vec<KeyValue>> {
+        debug!("LIST BY TAG SQL: {}", sql::LIST_BY_TAG_SQL.as_str());
+        let rows = sqlx::query_as::<_, KeyValue>(sql::LIST_BY_TAG_SQL.as_str())
+            .bind(tag)
+            .fetch_all(&self.pool).await?;
+        Ok(rows)
+    }
+
+    async fn get(&self, name: &str, revision: Option<Revision>) -> SumkinResult<Option<KeyValue>> {
+        let mut tx = self.pool.begin().await?;
+        let kv = Self::get_with_tx(&mut tx, name, revision).await?;
+        tx.commit().await?;
+
+        Ok(kv)
+    }
+
+    async fn get_range_bytes(&self, name: &str, offset: i64, length: i64) -> SumkinResult<Option<Vec<u8>>> {
+        debug!("GET RANGE BYTES SQL: {}", sql::GET_RANGE_BYTES_SQL);
+        let row = sqlx::query(sql::GET_RANGE_BYTES_SQL)
+            .bind(offset.max(0) + 1)
+            .bind(length.max(0))
+            .bind(name)
+            .fetch_optional(&self.pool).await?;
+        match row {
+            Some(row) => {
+                let bytes: Option<Vec<u8>> = row.try_get("value")?;
+                Ok(Some(bytes.unwrap_or_default()))
+            }
+            None => Ok(None)
+        }
+    }
+
+    async fn list_current(&self, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
+        let mut tx = self.pool.begin().await?;
+        let kvs = Self::list_current_with_tx(&mut tx, &self.config.prefix_match(prefix), self.config.list_limit(limit), include_deleted).await?;
+        tx.commit().await?;
+
+        Ok(kvs)
+
+    }
+
+    async fn clear(&self) -> SumkinResult<()> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        debug!("CLEAR SQL: {}", sql::CLEAR_SQL);
+        tx.execute(sql::CLEAR_SQL).await?;
+        Self::prune_unreferenced_with_tx(&mut tx).await?;
+        Self::bump_generation_with_tx(&mut tx).await?;
+        tx.commit().await?;
+        info!("Cleared the log");
+        Ok(())
+    }
+
+    async fn generation(&self) -> SumkinResult<u64> {
+        debug!("GENERATION SQL: {}", sql::GENERATION_SQL);
+        let generation: i64 = sqlx::query(sql::GENERATION_SQL).fetch_one(&self.pool).await?.try_get(0)?;
+        Ok(generation as u64)
+    }
+
+    async fn prune_log(&self, up_to_revision: Revision) -> SumkinResult<u64> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        debug!("PRUNE LOG SQL: {}", sql::PRUNE_LOG_SQL);
+        let removed = sqlx::query(sql::PRUNE_LOG_SQL).bind(up_to_revision).execute(&mut tx).await?.rows_affected();
+        self.set_compact_revision_with_tx(&mut tx, up_to_revision).await?;
+        Self::prune_unreferenced_with_tx(&mut tx).await?;
+        tx.commit().await?;
+        info!("Pruned {} log rows up to revision {}", removed, up_to_revision);
+        Ok(removed)
+    }
+
+    async fn compact_prefix(&self, prefix: &str, up_to_revision: Revision) -> SumkinResult<u64> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        debug!("COMPACT PREFIX SQL: {}", sql::COMPACT_PREFIX_SQL.as_str());
+        let removed = sqlx::query(sql::COMPACT_PREFIX_SQL.as_str())
+            .bind(up_to_revision)
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .execute(&mut tx).await?.rows_affected();
+        Self::prune_unreferenced_with_tx(&mut tx).await?;
+        tx.commit().await?;
+        info!("Pruned {} log rows under {} up to revision {}", removed, prefix, up_to_revision);
+        Ok(removed)
+    }
+
+    async fn delete(&self, name: &str) -> SumkinResult<Revision> {
+        let (_, revision) = self.delete_checked(name).await?;
+        Ok(revision)
+    }
+
+    async fn delete_checked(&self, name: &str) -> SumkinResult<(bool, Revision)> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let deleted = match Self::get_with_tx(&mut tx, name, None).await? {
+            Some(kv) => Some(self.tombstone_with_tx(&mut tx, &kv).await?),
+            None => None
+        };
+        let revision = match deleted {
+            Some(revision) => revision,
+            None => Self::current_revision_with_tx(&mut tx).await?
+        };
+        tx.commit().await?;
+        if deleted.is_some() {
+            self.notify(ChangeOp::Delete, name, revision);
+        }
+        Ok((deleted.is_some(), revision))
+    }
+
+    async fn delete_range_prev(&self, prefix: &str) -> SumkinResult<Vec<KeyValue>> {
+        let _permit = self.write_permit().await?;
+        let mut tx = self.pool.begin().await?;
+        let kvs = Self::list_current_with_tx(&mut tx, &self.config.prefix_match(prefix), -1, false).await?;
+        let mut revisions = Vec::with_capacity(kvs.len());
+        for kv in kvs.iter() {
+            revisions.push(self.tombstone_with_tx(&mut tx, kv).await?);
+        }
+        tx.commit().await?;
+        for (kv, revision) in kvs.iter().zip(revisions) {
+            self.notify(ChangeOp::Delete, kv.key(), revision);
+        }
+        Ok(kvs)
+    }
+
+    async fn changes_since(&self, prefix: &str, revision: Revision) -> SumkinResult<HashMap<String, KeyValue>> {
+        self.ensure_not_compacted(revision).await?;
+        debug!("CHANGES SINCE SQL: {}", sql::CHANGES_SINCE_SQL.as_str());
+        let kvs = sqlx::query_as::<_, KeyValue>(sql::CHANGES_SINCE_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(revision)
+            .fetch_all(&self.pool).await?;
+        Ok(kvs.into_iter().map(|kv| (kv.key().clone(), kv)).collect())
+    }
+
+    async fn recent(&self, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        debug!("RECENT SQL: {}", sql::RECENT_SQL.as_str());
+        let kvs = sqlx::query_as::<_, KeyValue>(sql::RECENT_SQL.as_str())
+            .bind(self.config.bound_list_limit(limit))
+            .fetch_all(&self.pool).await?;
+        Ok(kvs)
+    }
+
+    async fn recent_under(&self, prefix: &str, limit: i64) -> SumkinResult<Vec<KeyValue>> {
+        debug!("RECENT UNDER SQL: {}", sql::RECENT_UNDER_SQL.as_str());
+        let kvs = sqlx::query_as::<_, KeyValue>(sql::RECENT_UNDER_SQL.as_str())
+            .bind(self.config.prefix_match(prefix).like_pattern())
+            .bind(false)
+            .bind(self.config.bound_list_limit(limit))
+            .fetch_all(&self.pool).await?;
+        Ok(kvs)
+    }
+
+    async fn event_at(&self, revision: Revision) -> SumkinResult<Option<KeyValue>> {
+        debug!("GET REVISION SQL: {}", sql::GET_REVISION_SQL.as_str());
+        let kv = sqlx::query_as::<_, KeyValue>(sql::GET_REVISION_SQL.as_str())
+            .bind(revision)
+            .fetch_optional(&self.pool).await?;
+        Ok(kv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tracing_test::traced_test;
+    use tempfile::TempDir;
+    use crate::clock::MockClock;
+    use crate::keys::prefix_range_end;
+    use crate::traits::BoxedBackend;
+    use futures_util::StreamExt;
+    use crate::cache::CachedBackend;
+    use crate::buffer::BufferedBackend;
+    use crate::error::ErrorCode;
+
+    fn get_random_datasource(dir: &tempfile::TempDir) -> String {
+        let path = dir.path().join("state.db");
+
+        path.to_string_lossy().into_owned()
+
+    }
+
+    async fn get_backend(dir: &tempfile::TempDir) -> SqliteBackend {
+        let datasource = get_random_datasource(dir);
+        let pool_opts = SqlitePoolOptions::default();
+
+        SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap()
+    }
+
+    /// Runs `write` until it stops failing with `ErrorCode::Unavailable`, which racing write
+    /// transactions hit as SQLITE_LOCKED on the shared cache; the caller retries, as an
+    /// application would. Any other error panics.
+    async fn retry_unavailable<T, F, Fut>(mut write: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = SumkinResult<T>>
+    {
+        for _ in 0..50 {
+            match write().await {
+                Ok(result) => return result,
+                Err(e) if e.code() == ErrorCode::Unavailable => tokio::time::sleep(Duration::from_millis(5)).await,
+                Err(e) => panic!("{}", e),
+            }
+        }
+        panic!("Still unavailable after 50 tries")
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn super_basic() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+
+
+        let datasource = get_random_datasource(&temp_dir);
+        let pool_opts = SqlitePoolOptions::default();
+
+        let backend = SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap();
+
+        let _og_size = backend.size().await.unwrap();
+
+        let revision = backend.current_revision().await.unwrap();
+        assert_eq!(0, revision);
+
+
+        let count = backend.count("/").await.unwrap();
+        assert_eq!(0, count);
+
+        let list = backend.list_current("/", -1, false).await.unwrap();
+        assert_eq!(0, list.len());
+    }
+
+
+    #[tokio::test]
+    #[traced_test]
+    async fn solo_key_crud() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+
+        let datasource = get_random_datasource(&temp_dir);
+        let pool_opts = SqlitePoolOptions::default();
+
+        let backend = SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap();
+
+        let key = "/root/health";
+        let value = b"OK";
+        let null = backend.get(key, None).await.unwrap();
+        assert!(null.is_none());
+
+        let expected_revision = backend.put(key, value).await.unwrap();
+        assert_eq!(1, expected_revision);
+
+
+        let count = backend.count(key).await.unwrap();
+        assert_eq!(1, count);
+
+        let kv = backend.get(key, None).await.unwrap().unwrap();
+        assert_eq!(kv.value().as_ref().unwrap(), value);
+        assert_eq!(1, *kv.create_revision());
+        assert_eq!(1, *kv.mod_revision());
+
+        let new_value = b"NOT OKAY";
+
+        let expected_revision = backend.put(key, new_value).await.unwrap();
+        assert_eq!(2, expected_revision);
+
+        let count = backend.count(key).await.unwrap();
+        assert_eq!(1, count);
+
+        let kv = backend.get(key, None).await.unwrap().unwrap();
+        assert_eq!(kv.value().as_ref().unwrap(), new_value);
+        assert_eq!(1, *kv.create_revision());
+        assert_eq!(2, *kv.mod_revision());
+
+        let revision = backend.delete(key).await.unwrap();
+        assert_eq!(3, revision);
+
+        let kv = backend.get(key, None).await.unwrap();
+        assert!(kv.is_none());
+
+        let revision = backend.delete(key).await.unwrap();
+        assert_eq!(3, revision);
+        let count = backend.count(key).await.unwrap();
+        assert_eq!(0, count);
+    }
+
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_two() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+
+        let datasource = get_random_datasource(&temp_dir);
+        let pool_opts = SqlitePoolOptions::default();
+
+        let backend = SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap();
+
+        let key_1 = "/root/health";
+        let key_2 = "/root/status";
+        let value = b"OK";
+
+        let expected_revision = backend.put(key_1, value).await.unwrap();
+        assert_eq!(1, expected_revision);
+
+
+        let expected_revision = backend.put(key_2, value).await.unwrap();
+        assert_eq!(2, expected_revision);
+
+        let count = backend.count("/root/").await.unwrap();
+        assert_eq!(2, count);
+
+        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
+        assert_eq!(2, kvs.len());
+
+
+        let kvs = backend.list_current("/root/", 1, false).await.unwrap();
+        assert_eq!(1, kvs.len());
+
+
+        let expected_revision = backend.put(key_1, value).await.unwrap();
+        assert_eq!(3, expected_revision);
+
+
+        let expected_revision = backend.put(key_2, value).await.unwrap();
+        assert_eq!(4, expected_revision);
+
+        let count = backend.count("/root/").await.unwrap();
+        assert_eq!(2, count);
+
+        let revision = backend.delete("/root").await.unwrap();
+        assert_eq!(4, revision);
+
+        let revision = backend.delete(key_1).await.unwrap();
+        assert_eq!(5, revision);
+        let revision = backend.delete(key_1).await.unwrap();
+        assert_eq!(5, revision);
+        let count = backend.count("/root/").await.unwrap();
+        assert_eq!(1, count);
+        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
+        assert_eq!(1, kvs.len());
+
+
+
+        let revision = backend.delete(key_2).await.unwrap();
+        assert_eq!(6, revision);
+        let revision = backend.delete(key_2).await.unwrap();
+        assert_eq!(6, revision);
+        let count = backend.count("/root/").await.unwrap();
+        assert_eq!(0, count);
+        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
+        assert_eq!(0, kvs.len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_by_tag() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put_tagged("/root/a", "red", b"1").await.unwrap();
+        backend.put_tagged("/root/b", "blue", b"2").await.unwrap();
+        backend.put_tagged("/root/c", "red", b"3").await.unwrap();
+        backend.put("/root/d", b"4").await.unwrap();
+
+        let red = backend.list_by_tag("red").await.unwrap();
+        let keys: Vec<&str> = red.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/a", "/root/c"], keys);
+        assert_eq!(Some("red"), red[0].tag().as_deref());
+
+        let blue = backend.list_by_tag("blue").await.unwrap();
+        assert_eq!(1, blue.len());
+        assert_eq!("/root/b", blue[0].key());
+
+        backend.put_tagged("/root/c", "blue", b"3").await.unwrap();
+        assert_eq!(1, backend.list_by_tag("red").await.unwrap().len());
+        assert_eq!(2, backend.list_by_tag("blue").await.unwrap().len());
+
+        backend.delete("/root/b").await.unwrap();
+        assert_eq!(1, backend.list_by_tag("blue").await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn modified_between() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let clock = MockClock::new(1_000);
+        let backend = backend.with_clock(Arc::new(clock.clone()));
+
+        for key in ["/root/a", "/root/b", "/root/c", "/root/d"] {
+            backend.put(key, b"OK").await.unwrap();
+            clock.advance(1_000);
+        }
+        let kv = backend.get("/root/a", None).await.unwrap().unwrap();
+        assert_eq!(Some(1_000), *kv.mtime());
+
+        let kvs = backend.modified_between("/root/", 2_000, 4_000).await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/b", "/root/c"], keys);
+
+        let kvs = backend.modified_between("/other/", 0, i64::MAX).await.unwrap();
+        assert!(kvs.is_empty());
+
+        backend.delete("/root/b").await.unwrap();
+        let kvs = backend.modified_between("/root/", 2_000, 4_000).await.unwrap();
+        assert_eq!(1, kvs.len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn mock_clock_drives_mtime() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let clock = MockClock::new(5_000);
+        let backend = get_backend(&temp_dir).await.with_clock(Arc::new(clock.clone()));
+
+        backend.put("/root/health", b"OK").await.unwrap();
+        let kv = backend.get("/root/health", None).await.unwrap().unwrap();
+        assert_eq!(Some(5_000), *kv.mtime());
+
+        clock.advance(60 * 60 * 1_000);
+        backend.put("/root/health", b"STILL OK").await.unwrap();
+        let kv = backend.get("/root/health", None).await.unwrap().unwrap();
+        assert_eq!(Some(5_000 + 60 * 60 * 1_000), *kv.mtime());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn mock_clock_expires_leases() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let clock = MockClock::new(5_000);
+        let backend = get_backend(&temp_dir).await.with_clock(Arc::new(clock.clone()));
+
+        let lease = backend.grant_lease(10).await.unwrap();
+        backend.put("/session/a", b"OK").await.unwrap();
+        backend.attach_lease("/session/a", Some(lease)).await.unwrap();
+        backend.put("/session/b", b"OK").await.unwrap();
+
+        clock.advance(5_000);
+        backend.keep_alive(lease).await.unwrap();
+        clock.advance(9_000);
+        assert!(backend.revoke_expired().await.unwrap().is_empty());
+        assert!(backend.get("/session/a", None).await.unwrap().is_some());
+
+        clock.advance(1_000);
+        assert_eq!(vec![lease], backend.revoke_expired().await.unwrap());
+        assert!(backend.get("/session/a", None).await.unwrap().is_none());
+        assert!(backend.get("/session/b", None).await.unwrap().is_some());
+        assert!(backend.active_leases().await.unwrap().is_empty());
+        assert!(matches!(backend.keep_alive(lease).await, Err(Error::LeaseNotFound { .. })));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn count_all() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        assert_eq!((0, 0), backend.count_all("/root/").await.unwrap());
+
+        backend.put("/root/a", b"1").await.unwrap();
+        backend.put("/root/b", b"2").await.unwrap();
+        backend.put("/root/c", b"3").await.unwrap();
+        backend.delete("/root/b").await.unwrap();
+
+        assert_eq!((2, 1), backend.count_all("/root/").await.unwrap());
+        assert_eq!(2, backend.count("/root/").await.unwrap());
+
+        backend.put("/root/b", b"2").await.unwrap();
+        assert_eq!((3, 0), backend.count_all("/root/").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn prune_log() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/root/a", b"1").await.unwrap();
+        backend.put("/root/a", b"2").await.unwrap();
+        backend.put("/root/b", b"1").await.unwrap();
+        backend.put("/root/c", b"1").await.unwrap();
+        backend.delete("/root/c").await.unwrap();
+        let revision = backend.put("/root/a", b"3").await.unwrap();
+        assert_eq!(6, revision);
+
+        // Rows 1 and 2 are superseded by 6, 4 and 5 belong to the deleted /root/c.
+        let removed = backend.prune_log(5.into()).await.unwrap();
+        assert_eq!(4, removed);
+
+        let raw: i64 = sqlx::query("SELECT COUNT(*) FROM sumkin WHERE name LIKE '/root/%'")
+            .fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!(2, raw);
+
+        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
+        let values: Vec<(&str, &[u8])> = kvs.iter().map(|kv| (kv.key().as_str(), kv.value().as_deref().unwrap())).collect();
+        assert_eq!(vec![("/root/b", &b"1"[..]), ("/root/a", &b"3"[..])], values);
+
+        assert_eq!(5, backend.compact_revision().await.unwrap());
+
+        assert_eq!(0, backend.prune_log(5.into()).await.unwrap());
+        assert_eq!(5, backend.compact_revision().await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn stat() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        assert!(backend.stat("/root/health").await.unwrap().is_none());
+
+        backend.put("/root/health", b"OK").await.unwrap();
+        let revision = backend.put("/root/health", b"NOT OKAY").await.unwrap();
+
+        let stat = backend.stat("/root/health").await.unwrap().unwrap();
+        assert_eq!(1, *stat.create_revision());
+        assert_eq!(revision, *stat.mod_revision());
+        assert_eq!(2, *stat.version());
+        assert_eq!(8, *stat.value_size());
+        assert_eq!(None, *stat.lease());
+
+        backend.delete("/root/health").await.unwrap();
+        assert!(backend.stat("/root/health").await.unwrap().is_none());
+
+        backend.put("/root/health", b"OK").await.unwrap();
+        let stat = backend.stat("/root/health").await.unwrap().unwrap();
+        assert_eq!(1, *stat.version());
+        assert_eq!(2, *stat.value_size());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn config_pragmas() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new()
+            .cache_size(-4096)
+            .mmap_size(1 << 20);
+
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        let mut conn = backend.pool.acquire().await.unwrap();
+        let cache_size: i64 = sqlx::query("PRAGMA cache_size").fetch_one(&mut conn).await.unwrap().get(0);
+        assert_eq!(-4096, cache_size);
+        let mmap_size: i64 = sqlx::query("PRAGMA mmap_size").fetch_one(&mut conn).await.unwrap().get(0);
+        assert_eq!(1 << 20, mmap_size);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn config_wal_autocheckpoint() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().wal_autocheckpoint(250);
+
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        let mut conn = backend.pool.acquire().await.unwrap();
+        let pages: i64 = sqlx::query("PRAGMA wal_autocheckpoint").fetch_one(&mut conn).await.unwrap().get(0);
+        assert_eq!(250, pages);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn pragmas_apply_to_every_connection() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new()
+            .busy_timeout(Duration::from_millis(1234))
+            .foreign_keys(true)
+            .cache_size(-4096);
+        let pool_opts = SqlitePoolOptions::new().max_connections(4);
+
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), pool_opts, config).await.unwrap();
+
+        let mut conns = Vec::new();
+        for _ in 0..4 {
+            conns.push(backend.pool.acquire().await.unwrap());
+        }
+        assert_eq!(4, backend.pool.size());
+
+        for conn in conns.iter_mut() {
+            let journal_mode: String = sqlx::query("PRAGMA journal_mode").fetch_one(&mut *conn).await.unwrap().get(0);
+            assert_eq!("wal", journal_mode);
+            let busy_timeout: i64 = sqlx::query("PRAGMA busy_timeout").fetch_one(&mut *conn).await.unwrap().get(0);
+            assert_eq!(1234, busy_timeout);
+            let foreign_keys: i64 = sqlx::query("PRAGMA foreign_keys").fetch_one(&mut *conn).await.unwrap().get(0);
+            assert_eq!(1, foreign_keys);
+            let cache_size: i64 = sqlx::query("PRAGMA cache_size").fetch_one(&mut *conn).await.unwrap().get(0);
+            assert_eq!(-4096, cache_size);
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn range_keys() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for key in ["/root/b", "/root/a", "/root/a/child", "/rootless", "/other"] {
+            backend.put(key, b"OK").await.unwrap();
+        }
+        backend.delete("/other").await.unwrap();
+        backend.prune_log(backend.current_revision().await.unwrap()).await.unwrap();
+
+        let keys = |kvs: Vec<KeyValue>| kvs.into_iter().map(|kv| kv.key().clone()).collect::<Vec<_>>();
+
+        let single = backend.range_keys(b"/root/a", b"/root/a\0", -1).await.unwrap();
+        assert_eq!(vec!["/root/a"], keys(single));
+        let single = backend.range_keys(b"/root/a", b"", -1).await.unwrap();
+        assert_eq!(vec!["/root/a"], keys(single));
+
+        let prefix = backend.range_keys(b"/root/", &prefix_range_end(b"/root/"), -1).await.unwrap();
+        assert_eq!(vec!["/root/a", "/root/a/child", "/root/b"], keys(prefix));
+
+        let limited = backend.range_keys(b"/root/", &prefix_range_end(b"/root/"), 2).await.unwrap();
+        assert_eq!(vec!["/root/a", "/root/a/child"], keys(limited));
+
+        let all = backend.range_keys(b"\0", b"\0", -1).await.unwrap();
+        assert_eq!(vec!["/root/a", "/root/a/child", "/root/b", "/rootless"], keys(all));
+
+        assert!(backend.range_keys(b"\xff", b"", -1).await.is_err());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn count_range() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for key in ["/root/a", "/root/b", "/root/c", "/other"] {
+            backend.put(key, b"OK").await.unwrap();
+        }
+        backend.put("/root/a", b"AGAIN").await.unwrap();
+        backend.delete("/root/c").await.unwrap();
+        backend.prune_log(backend.current_revision().await.unwrap()).await.unwrap();
+
+        let count = backend.count_range(b"/root/", &prefix_range_end(b"/root/")).await.unwrap();
+        assert_eq!(backend.count("/root/").await.unwrap(), count);
+        assert_eq!(2, count);
+
+        assert_eq!(1, backend.count_range(b"/root/a", b"/root/b").await.unwrap());
+        assert_eq!(1, backend.count_range(b"/root/b", b"").await.unwrap());
+        assert_eq!(3, backend.count_range(b"\0", b"\0").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn returned_revisions_match_log() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let created = backend.put("/root/health", b"OK").await.unwrap();
+        let updated = backend.put_tagged("/root/health", "status", b"NOT OKAY").await.unwrap();
+        let deleted = backend.delete("/root/health").await.unwrap();
+        assert!(created < updated && updated < deleted);
+
+        let kv = backend.event_at(created).await.unwrap().unwrap();
+        assert_eq!("/root/health", kv.key());
+        assert_eq!(created, *kv.mod_revision());
+        assert_eq!(b"OK", kv.value().as_deref().unwrap());
+        assert!(!kv.deleted());
+
+        let kv = backend.event_at(updated).await.unwrap().unwrap();
+        assert_eq!(updated, *kv.mod_revision());
+        assert_eq!(b"NOT OKAY", kv.value().as_deref().unwrap());
+
+        let kv = backend.event_at(deleted).await.unwrap().unwrap();
+        assert_eq!("/root/health", kv.key());
+        assert!(kv.deleted());
+
+        // A no-op delete reports the revision the store is at when it committed.
+        let noop = backend.delete("/root/health").await.unwrap();
+        assert_eq!(deleted, noop);
+        assert!(backend.event_at(deleted.next()).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn recent() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/root/a", b"1").await.unwrap();
+        backend.put("/root/b", b"1").await.unwrap();
+        backend.put("/other/c", b"1").await.unwrap();
+        backend.put("/root/a", b"2").await.unwrap();
+        backend.delete("/root/b").await.unwrap();
+
+        let kvs = backend.recent(3).await.unwrap();
+        let events: Vec<(i64, &str, bool)> = kvs.iter().map(|kv| ((*kv.mod_revision()).into(), kv.key().as_str(), *kv.deleted())).collect();
+        assert_eq!(vec![(5, "/root/b", true), (4, "/root/a", false), (3, "/other/c", false)], events);
+
+        assert_eq!(5, backend.recent(10).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn import_kine() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let options = SqliteConnectOptions::new()
+            .filename(&datasource)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::default().connect_with(options).await.unwrap();
+
+        pool.execute(r###"
+            CREATE TABLE kine
+            (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name INTEGER,
+                created INTEGER,
+                deleted INTEGER,
+                create_revision INTEGER,
+                prev_revision INTEGER,
+                lease INTEGER,
+                value BLOB,
+                old_value BLOB
+            )
+        "###).await.unwrap();
+        pool.execute(r###"
+            INSERT INTO kine(id, name, created, deleted, create_revision, prev_revision, lease, value, old_value) VALUES
+                (1, 'compact_rev_key', 1, 0, 0, 0, 0, '', ''),
+                (2, '/registry/a', 1, 0, 0, 0, 0, 'one', NULL),
+                (3, '/registry/b', 1, 0, 0, 0, 0, 'two', NULL),
+                (7, '/registry/a', 0, 0, 2, 2, 0, 'uno', 'one')
+        "###).await.unwrap();
+
+        let backend = SqliteBackend::with_pool(pool).await.unwrap();
+
+        assert_eq!(7, backend.current_revision().await.unwrap());
+        let kv = backend.get("/registry/a", None).await.unwrap().unwrap();
+        assert_eq!(b"uno", kv.value().as_deref().unwrap());
+        assert_eq!(7, *kv.mod_revision());
+        assert_eq!(2, backend.count("/registry/").await.unwrap());
+
+        assert_eq!(8, backend.put("/registry/c", b"three").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_empty_values() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/root/full", b"OK").await.unwrap();
+        backend.put("/root/empty", b"").await.unwrap();
+        backend.put("/root/emptied", b"OK").await.unwrap();
+        backend.put("/root/emptied", b"").await.unwrap();
+        backend.put("/root/refilled", b"").await.unwrap();
+        backend.put("/root/refilled", b"OK").await.unwrap();
+
+        let kvs = backend.list_empty_values("/root/").await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/empty", "/root/emptied"], keys);
+
+        backend.delete("/root/empty").await.unwrap();
+        assert_eq!(1, backend.list_empty_values("/root/").await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn watch_all() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let first = backend.put("/a/one", b"1").await.unwrap();
+        let watch = backend.watch_all(first, false);
+        futures_util::pin_mut!(watch);
+
+        let writer = backend.clone();
+        tokio::spawn(async move {
+            writer.put("/b/two", b"2").await.unwrap();
+            writer.delete("/a/one").await.unwrap();
+        });
+
+        let mut events = Vec::new();
+        for _ in 0..3 {
+            let event = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+            events.push(event);
+        }
+
+        let seen: Vec<(i64, &str)> = events.iter().map(|e| e.kv().unwrap()).map(|kv| ((*kv.mod_revision()).into(), kv.key().as_str())).collect();
+        assert_eq!(vec![(1, "/a/one"), (2, "/b/two"), (3, "/a/one")], seen);
+        assert!(matches!(events[0], WatchEvent::Put { .. }));
+        assert!(matches!(events[2], WatchEvent::Delete { .. }));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn watch_all_from_now() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a/old", b"1").await.unwrap();
+        let watch = backend.watch_all(0.into(), false);
+        futures_util::pin_mut!(watch);
+
+        // Nothing new has been written yet, so the first poll must come up empty.
+        assert!(tokio::time::timeout(Duration::from_millis(200), watch.next()).await.is_err());
+
+        backend.put("/b/new", b"2").await.unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!("/b/new", event.kv().unwrap().key());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn get_first() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/env/default/x", b"default").await.unwrap();
+        backend.put("/env/staging/x", b"staging").await.unwrap();
+
+        let (name, kv) = backend.get_first(&["/env/prod/x", "/env/default/x", "/env/staging/x"]).await.unwrap().unwrap();
+        assert_eq!("/env/default/x", name);
+        assert_eq!(b"default", kv.value().as_deref().unwrap());
+
+        backend.put("/env/prod/x", b"prod").await.unwrap();
+        let (name, _) = backend.get_first(&["/env/prod/x", "/env/default/x"]).await.unwrap().unwrap();
+        assert_eq!("/env/prod/x", name);
+
+        assert!(backend.get_first(&["/env/dev/x"]).await.unwrap().is_none());
+        assert!(backend.get_first(&[]).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_by_content_type() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put_with_meta("/root/a", b"{}", "application/json").await.unwrap();
+        backend.put_with_meta("/root/b", b"hello", "text/plain").await.unwrap();
+        backend.put_with_meta("/root/c", b"[]", "application/json").await.unwrap();
+        backend.put("/root/d", b"raw").await.unwrap();
+
+        let kv = backend.get("/root/b", None).await.unwrap().unwrap();
+        assert_eq!(Some("text/plain"), kv.content_type().as_deref());
+        let kv = backend.get("/root/d", None).await.unwrap().unwrap();
+        assert_eq!(None, *kv.content_type());
+
+        let json = backend.list_by_content_type("application/json").await.unwrap();
+        let keys: Vec<&str> = json.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/a", "/root/c"], keys);
+
+        backend.put("/root/c", b"no longer json").await.unwrap();
+        assert_eq!(1, backend.list_by_content_type("application/json").await.unwrap().len());
+        assert_eq!(1, backend.list_by_content_type("text/plain").await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn is_empty() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        assert!(backend.is_empty().await.unwrap());
+
+        backend.put("/root/health", b"OK").await.unwrap();
+        assert!(!backend.is_empty().await.unwrap());
+
+        backend.delete("/root/health").await.unwrap();
+        assert!(backend.is_empty().await.unwrap());
+
+        backend.prune_log(backend.current_revision().await.unwrap()).await.unwrap();
+        assert!(backend.is_empty().await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn prefix_exists() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        assert!(!backend.prefix_exists("/root/").await.unwrap());
+
+        backend.put("/root/health", b"OK").await.unwrap();
+        assert!(backend.prefix_exists("/root/").await.unwrap());
+        assert!(!backend.prefix_exists("/other/").await.unwrap());
+
+        backend.delete("/root/health").await.unwrap();
+        assert!(!backend.prefix_exists("/root/").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn watch_reports_compaction() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a/one", b"1").await.unwrap();
+        backend.put("/a/one", b"2").await.unwrap();
+        backend.put("/a/one", b"3").await.unwrap();
+
+        let watch = backend.watch_all(1.into(), false);
+        futures_util::pin_mut!(watch);
+
+        backend.prune_log(3.into()).await.unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+        assert!(matches!(event, WatchEvent::Compacted { compact_revision } if compact_revision == 3));
+        assert!(tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().is_none());
+
+        // A watch that starts past the compaction point is unaffected.
+        let watch = backend.watch_all(4.into(), false);
+        futures_util::pin_mut!(watch);
+        backend.put("/a/one", b"4").await.unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+        assert!(matches!(event, WatchEvent::Put { .. }));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn literal_prefix_vs_pattern() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for key in ["/root/a_b", "/root/axb", "/root/100%", "/root/1000", "/rootless"] {
+            backend.put(key, b"OK").await.unwrap();
+        }
+
+        let keys = |kvs: Vec<KeyValue>| kvs.into_iter().map(|kv| kv.key().clone()).collect::<Vec<_>>();
+
+        assert_eq!(vec!["/root/a_b"], keys(backend.list_current("/root/a_b", -1, false).await.unwrap()));
+        assert_eq!(1, backend.count("/root/a_b").await.unwrap());
+        assert_eq!(vec!["/root/100%"], keys(backend.list_current("/root/100%", -1, false).await.unwrap()));
+        assert!(backend.list_current("/root%", -1, false).await.unwrap().is_empty());
+        assert_eq!(0, backend.count("/root%").await.unwrap());
+
+        assert_eq!(vec!["/root/a_b", "/root/axb"], keys(backend.list_matching("/root/a_b", -1).await.unwrap()));
+        assert_eq!(vec!["/root/100%", "/root/1000"], keys(backend.list_matching("/root/100%", -1).await.unwrap()));
+        assert_eq!(vec!["/root/100%"], keys(backend.list_matching("/root/100\\%", -1).await.unwrap()));
+        assert_eq!(5, backend.list_matching("/root%", -1).await.unwrap().len());
+        assert_eq!(2, backend.list_matching("/root%", 2).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn recent_under() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/cfg/a", b"1").await.unwrap();
+        backend.put("/cfg/b", b"1").await.unwrap();
+        backend.put("/cfg/c", b"1").await.unwrap();
+        backend.put("/other/x", b"1").await.unwrap();
+        backend.put("/cfg/a", b"2").await.unwrap();
+        backend.delete("/cfg/c").await.unwrap();
+
+        let kvs = backend.recent_under("/cfg/", 2).await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/cfg/a", "/cfg/b"], keys);
+        assert_eq!(5, *kvs[0].mod_revision());
+
+        assert_eq!(2, backend.recent_under("/cfg/", -1).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn put_batch_update() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/creds/a", b"old-a").await.unwrap();
+        backend.put("/creds/b", b"old-b").await.unwrap();
+
+        let revisions = backend.put_batch_update(&[("/creds/a", b"new-a"), ("/creds/b", b"new-b")]).await.unwrap();
+        assert_eq!(vec![3, 4], revisions);
+
+        let a = backend.get("/creds/a", None).await.unwrap().unwrap();
+        assert_eq!(b"new-a", a.value().as_deref().unwrap());
+        assert_eq!(1, *a.create_revision());
+        let b = backend.get("/creds/b", None).await.unwrap().unwrap();
+        assert_eq!(b"new-b", b.value().as_deref().unwrap());
+        assert_eq!(2, *b.create_revision());
+
+        let err = backend.put_batch_update(&[("/creds/a", b"newer-a"), ("/creds/missing", b"x")]).await.unwrap_err();
+        assert!(matches!(err, Error::KeyNotFound { ref name } if name == "/creds/missing"));
+        let a = backend.get("/creds/a", None).await.unwrap().unwrap();
+        assert_eq!(b"new-a", a.value().as_deref().unwrap());
+        assert_eq!(4, backend.current_revision().await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn snapshot_pages() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for key in ["/root/c", "/root/a", "/root/b"] {
+            backend.put(key, b"before").await.unwrap();
+        }
+
+        let mut snapshot = backend.snapshot(None).await.unwrap();
+        assert_eq!(3, snapshot.revision());
+
+        backend.put("/root/a", b"after").await.unwrap();
+        backend.delete("/root/b").await.unwrap();
+        backend.put("/root/d", b"after").await.unwrap();
+
+        let page = snapshot.next_page(2).await.unwrap();
+        let keys: Vec<&str> = page.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/a", "/root/b"], keys);
+        assert!(page.iter().all(|kv| kv.value().as_deref() == Some(&b"before"[..])));
+
+        let page = snapshot.next_page(2).await.unwrap();
+        let keys: Vec<&str> = page.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/c"], keys);
+        assert!(snapshot.next_page(2).await.unwrap().is_empty());
+
+        let mut older = backend.snapshot(Some(1.into())).await.unwrap();
+        let page = older.next_page(10).await.unwrap();
+        assert_eq!(1, page.len());
+        assert_eq!("/root/c", page[0].key());
+
+        backend.prune_log(5.into()).await.unwrap();
+        assert!(matches!(older.next_page(10).await, Err(Error::Compacted { revision, compact_revision }) if revision == 1 && compact_revision == 5));
+        assert!(matches!(backend.snapshot(Some(2.into())).await, Err(Error::Compacted { .. })));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn boxed_backend() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend: BoxedBackend = get_backend(&temp_dir).await.into_boxed();
+
+        let revision = backend.put("/root/health", b"OK").await.unwrap();
+        assert_eq!(1, revision);
+        let kv = backend.get("/root/health", None).await.unwrap().unwrap();
+        assert_eq!(b"OK", kv.value().as_deref().unwrap());
+        assert_eq!(1, backend.count("/root/").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn delete_range_prev() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/app/a", b"alpha").await.unwrap();
+        backend.put("/app/b", b"beta").await.unwrap();
+        backend.put("/app/c", b"gamma").await.unwrap();
+        backend.put("/app/b", b"beta2").await.unwrap();
+        backend.put("/other", b"keep").await.unwrap();
+
+        let prev = backend.delete_range_prev("/app/").await.unwrap();
+        let prev: Vec<(&str, &[u8], i64)> = prev.iter().map(|kv| (kv.key().as_str(), kv.value().as_deref().unwrap(), (*kv.mod_revision()).into())).collect();
+        assert_eq!(vec![("/app/a", &b"alpha"[..], 1), ("/app/c", &b"gamma"[..], 3), ("/app/b", &b"beta2"[..], 4)], prev);
+
+        assert_eq!(0, backend.count("/app/").await.unwrap());
+        assert_eq!(1, backend.count("/other").await.unwrap());
+        assert_eq!(8, backend.current_revision().await.unwrap());
+
+        assert!(backend.delete_range_prev("/app/").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn attach_lease() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put_with_meta("/svc/node", b"10.0.0.1", "text/plain").await.unwrap();
+        let lease = backend.grant_lease(30).await.unwrap();
+
+        let attached = backend.attach_lease("/svc/node", Some(lease)).await.unwrap();
+        let kv = backend.get("/svc/node", None).await.unwrap().unwrap();
+        assert_eq!(attached, *kv.mod_revision());
+        assert_eq!(Some(lease), *kv.lease());
+        assert_eq!(b"10.0.0.1", kv.value().as_deref().unwrap());
+        assert_eq!(Some("text/plain"), kv.content_type().as_deref());
+        assert_eq!(1, *kv.create_revision());
+
+        let detached = backend.attach_lease("/svc/node", None).await.unwrap();
+        assert!(detached > attached);
+        let kv = backend.get("/svc/node", None).await.unwrap().unwrap();
+        assert_eq!(None, *kv.lease());
+        assert_eq!(b"10.0.0.1", kv.value().as_deref().unwrap());
+
+        let err = backend.attach_lease("/svc/node", Some(lease + 100)).await.unwrap_err();
+        assert!(matches!(err, Error::LeaseNotFound { .. }));
+        let err = backend.attach_lease("/svc/missing", Some(lease)).await.unwrap_err();
+        assert!(matches!(err, Error::KeyNotFound { .. }));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn size_breakdown() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+        backend.put("/root/health", b"OK").await.unwrap();
+
+        let breakdown = backend.size_breakdown().await.unwrap();
+        if breakdown.is_empty() {
+            // SQLite built without dbstat.
+            return;
+        }
+        let names: Vec<&str> = breakdown.iter().map(|(name, _)| name.as_str()).collect();
+        assert!(names.contains(&"sumkin"));
+        assert!(names.contains(&"sumkin_name_index"));
+        assert!(breakdown.iter().all(|(_, size)| *size > 0));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn count_skips_values() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let value = vec![7u8; 64 * 1024];
+        for i in 0..50 {
+            backend.put(&format!("/blobs/{}", i), &value).await.unwrap();
+        }
+        assert_eq!(50, backend.count("/blobs/").await.unwrap());
+        assert_eq!(50, backend.count_range(b"/blobs/", &prefix_range_end(b"/blobs/")).await.unwrap());
+
+        for sql in [sql::COUNT_SQL.to_string(), sql::COUNT_RANGE_SQL.replace("{}", "")] {
+            let plan: Vec<String> = sqlx::query(&format!("EXPLAIN QUERY PLAN {}", sql))
+                .fetch_all(&backend.pool).await.unwrap()
+                .iter().map(|row| row.get("detail")).collect();
+            // No row materialization or sorting: only ids flow through the query.
+            assert!(plan.iter().all(|line| !line.contains("CO-ROUTINE") && !line.contains("TEMP B-TREE")), "{:?}", plan);
+        }
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn explain() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+        backend.put("/root/health", b"OK").await.unwrap();
+
+        let plan = backend.explain("/root/").await.unwrap();
+        assert!(plan.iter().any(|line| line.contains("sumkin_name")), "{:?}", plan);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn name_column_becomes_text() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let options = SqliteConnectOptions::new()
+            .filename(&datasource)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::default().connect_with(options).await.unwrap();
+
+        // Lay out the database as it was right before the rebuild.
+        let rebuild = MIGRATIONS.iter().position(|m| m.contains("sumkin_new")).unwrap();
+        for migration in SCHEMA.iter().chain(&MIGRATIONS[..rebuild]) {
+            pool.execute(*migration).await.unwrap();
+        }
+        pool.execute(format!("PRAGMA user_version = {}", rebuild).as_str()).await.unwrap();
+        pool.execute(r###"
+            INSERT INTO sumkin(id, name, created, deleted, create_revision, prev_revision, lease, value, old_value) VALUES
+                (1, '/root/health', 1, 0, 1, NULL, NULL, 'OK', NULL),
+                (2, '42', 1, 0, 2, NULL, NULL, 'answer', NULL),
+                (9, '/root/gone', 1, 0, 9, NULL, NULL, 'x', NULL);
+            DELETE FROM sumkin WHERE id = 9;
+        "###).await.unwrap();
+        let stored_type: String = sqlx::query("SELECT typeof(name) FROM sumkin WHERE id = 2").fetch_one(&pool).await.unwrap().get(0);
+        assert_eq!("integer", stored_type);
+
+        let backend = SqliteBackend::with_pool(pool).await.unwrap();
+
+        let declared: String = sqlx::query("SELECT type FROM pragma_table_info('sumkin') WHERE name = 'name'")
+            .fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!("TEXT", declared);
+        let stored_type: String = sqlx::query("SELECT typeof(name) FROM sumkin WHERE id = 2").fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!("text", stored_type);
+
+        let kv = backend.get("/root/health", None).await.unwrap().unwrap();
+        assert_eq!(b"OK", kv.value().as_deref().unwrap());
+        let kv = backend.get("42", None).await.unwrap().unwrap();
+        assert_eq!("42", kv.key());
+        assert_eq!(b"answer", kv.value().as_deref().unwrap());
+
+        assert_eq!(10, backend.put("/root/new", b"OK").await.unwrap());
+        let plan = backend.explain("/root/").await.unwrap();
+        assert!(plan.iter().any(|line| line.contains("sumkin_name")), "{:?}", plan);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn put_batch_coalesce() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let entries: &[(&str, &[u8])] = &[("/root/a", b"1"), ("/root/a", b"2"), ("/root/a", b"3")];
+
+        let revisions = backend.put_batch(entries, true).await.unwrap();
+        assert_eq!(vec![1, 1, 1], revisions);
+        assert_eq!(1, backend.current_revision().await.unwrap());
+        let kv = backend.get("/root/a", None).await.unwrap().unwrap();
+        assert_eq!(b"3", kv.value().as_deref().unwrap());
+
+        let revisions = backend.put_batch(entries, false).await.unwrap();
+        assert_eq!(vec![2, 3, 4], revisions);
+
+        let entries: &[(&str, &[u8])] = &[("/root/a", b"4"), ("/root/b", b"1"), ("/root/a", b"5"), ("/root/a", b"6")];
+        let revisions = backend.put_batch(entries, true).await.unwrap();
+        assert_eq!(vec![5, 6, 7, 7], revisions);
+        let kv = backend.get("/root/a", None).await.unwrap().unwrap();
+        assert_eq!(b"6", kv.value().as_deref().unwrap());
+        assert_eq!(1, *kv.create_revision());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_map() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/app/a", b"1").await.unwrap();
+        backend.put("/app/b", b"2").await.unwrap();
+        backend.put("/app/a", b"3").await.unwrap();
+        backend.put("/other", b"4").await.unwrap();
+
+        let map = backend.list_map("/app/", 10).await.unwrap();
+        let mut keys: Vec<&str> = map.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(vec!["/app/a", "/app/b"], keys);
+        assert_eq!(Some(&b"3"[..]), map["/app/a"].value().as_deref());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn watch_key() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let watch = backend.watch_key("/election/leader", 1.into(), false);
+        futures_util::pin_mut!(watch);
+
+        backend.put("/election/leader", b"a").await.unwrap();
+        backend.put("/election/leader-2", b"x").await.unwrap();
+        backend.put("/other", b"y").await.unwrap();
+        backend.delete("/election/leader").await.unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), watch.next()).await.unwrap().unwrap().unwrap();
+            events.push(event);
+        }
+        let seen: Vec<(i64, &str)> = events.iter().map(|e| e.kv().unwrap()).map(|kv| ((*kv.mod_revision()).into(), kv.key().as_str())).collect();
+        assert_eq!(vec![(1, "/election/leader"), (4, "/election/leader")], seen);
+        assert!(matches!(events[1], WatchEvent::Delete { .. }));
+
+        backend.put("/other", b"z").await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), watch.next()).await.is_err());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn try_lock() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let contenders: Vec<_> = (0..2).map(|_| {
+            let backend = backend.clone();
+            tokio::spawn(async move { backend.try_lock("/locks/leader", 30).await.unwrap() })
+        }).collect();
+        let mut guards = Vec::new();
+        for contender in contenders {
+            guards.extend(contender.await.unwrap());
+        }
+        assert_eq!(1, guards.len());
+        let guard = guards.pop().unwrap();
+
+        let kv = backend.get("/locks/leader", None).await.unwrap().unwrap();
+        assert_eq!(Some(guard.lease_id()), *kv.lease());
+        assert!(backend.try_lock("/locks/leader", 30).await.unwrap().is_none());
+        backend.keep_alive(guard.lease_id()).await.unwrap();
+
+        let lease_id = guard.lease_id();
+        guard.release().await.unwrap();
+        assert!(backend.get("/locks/leader", None).await.unwrap().is_none());
+        assert!(matches!(backend.keep_alive(lease_id).await, Err(Error::LeaseNotFound { .. })));
+
+        let guard = backend.try_lock("/locks/leader", 30).await.unwrap().unwrap();
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while backend.get("/locks/leader", None).await.unwrap().is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn try_lock_takes_over_expired_lock() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let clock = MockClock::new(1_000);
+        let backend = get_backend(&temp_dir).await.with_clock(Arc::new(clock.clone()));
+        let leases = || async { sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM leases").fetch_one(&backend.pool).await.unwrap() };
+
+        let stale = backend.try_lock("/locks/leader", 30).await.unwrap().unwrap();
+        assert!(backend.try_lock("/locks/leader", 30).await.unwrap().is_none());
+        assert_eq!(1, leases().await);
+
+        clock.advance(31_000);
+        let guard = backend.try_lock("/locks/leader", 30).await.unwrap().unwrap();
+        assert_ne!(stale.lease_id(), guard.lease_id());
+        assert_eq!(1, leases().await);
+        assert!(matches!(backend.keep_alive(stale.lease_id()).await, Err(Error::LeaseNotFound { .. })));
+
+        stale.release().await.unwrap();
+        let kv = backend.get("/locks/leader", None).await.unwrap().unwrap();
+        assert_eq!(Some(guard.lease_id()), *kv.lease());
+        guard.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_stale() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let clock = MockClock::new(1_000);
+        let backend = backend.with_clock(Arc::new(clock.clone()));
+
+        backend.put("/jobs/old", b"1").await.unwrap();
+        backend.put("/jobs/refreshed", b"1").await.unwrap();
+        clock.advance(10_000);
+        backend.put("/jobs/refreshed", b"2").await.unwrap();
+        backend.put("/jobs/new", b"1").await.unwrap();
+        clock.advance(1_000);
+
+        let kvs = backend.list_stale("/jobs/", 5_000).await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/jobs/old"], keys);
+
+        assert_eq!(3, backend.list_stale("/jobs/", 0).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn get_map() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/cfg/a", b"1").await.unwrap();
+        backend.put("/cfg/b", b"2").await.unwrap();
+        backend.put("/cfg/c", b"3").await.unwrap();
+        backend.delete("/cfg/c").await.unwrap();
+
+        let map = backend.get_map(&["/cfg/a", "/cfg/b", "/cfg/c", "/cfg/missing"]).await.unwrap();
+        let mut keys: Vec<&str> = map.keys().map(String::as_str).collect();
+        keys.sort_unstable();
+        assert_eq!(vec!["/cfg/a", "/cfg/b"], keys);
+        assert_eq!(Some(&b"2"[..]), map["/cfg/b"].value().as_deref());
+
+        // More names than fit in one query.
+        let names: Vec<String> = (0..GET_MAP_CHUNK_SIZE + 10).map(|i| format!("/cfg/{}", i)).collect();
+        let mut names: Vec<&str> = names.iter().map(String::as_str).collect();
+        names.push("/cfg/a");
+        assert_eq!(1, backend.get_map(&names).await.unwrap().len());
+
+        backend.prune_log(backend.current_revision().await.unwrap()).await.unwrap();
+        assert!(backend.get_map(&["compact_rev_key"]).await.unwrap().is_empty());
+        assert!(backend.get("compact_rev_key", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn schema_is_atomic() {
+        let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+
+        let schema = ["CREATE TABLE one (id INTEGER)", "CREATE INDEX one_index ON one (id)"];
+        let migrations = ["CREATE TABLE two (id INTEGER)", "ALTER TABLE one ADD COLUMN name TEXT", "ALTER TABLE missing ADD COLUMN name TEXT"];
+        assert!(SqliteBackend::apply_schema(&pool, &schema, &migrations).await.is_err());
+
+        let objects: i64 = sqlx::query("SELECT COUNT(*) FROM sqlite_master").fetch_one(&pool).await.unwrap().try_get(0).unwrap();
+        assert_eq!(0, objects);
+        let user_version: i64 = sqlx::query(sql::USER_VERSION_SQL).fetch_one(&pool).await.unwrap().try_get(0).unwrap();
+        assert_eq!(0, user_version);
+
+        SqliteBackend::apply_schema(&pool, &schema, &migrations[..2]).await.unwrap();
+        let user_version: i64 = sqlx::query(sql::USER_VERSION_SQL).fetch_one(&pool).await.unwrap().try_get(0).unwrap();
+        assert_eq!(2, user_version);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn per_key_retention() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().per_key_retention(2);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        for value in [b"1", b"2", b"3", b"4", b"5"] {
+            backend.put("/retained", value).await.unwrap();
+        }
+        backend.put("/other", b"1").await.unwrap();
+        backend.put("/other", b"2").await.unwrap();
+
+        let rows: Vec<(i64, Vec<u8>)> = sqlx::query_as("SELECT id, value FROM sumkin WHERE name = '/retained' ORDER BY id")
+            .fetch_all(&backend.pool).await.unwrap();
+        assert_eq!(vec![(3, b"3".to_vec()), (4, b"4".to_vec()), (5, b"5".to_vec())], rows);
+        assert_eq!(5, *backend.get("/retained", None).await.unwrap().unwrap().mod_revision());
+        assert!(backend.event_at(1.into()).await.unwrap().is_none());
+        assert!(backend.event_at(6.into()).await.unwrap().is_some());
+
+        let lease = backend.grant_lease(30).await.unwrap();
+        backend.attach_lease("/retained", Some(lease)).await.unwrap();
+        backend.attach_lease("/retained", None).await.unwrap();
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM sumkin WHERE name = '/retained' ORDER BY id")
+            .fetch_all(&backend.pool).await.unwrap();
+        assert_eq!(vec![5, 8, 9], ids);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn export_ndjson() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/app/a", b"alpha").await.unwrap();
+        backend.put_with_meta("/app/b", b"{}", "application/json").await.unwrap();
+        backend.put("/app/c", &[0, 159, 255]).await.unwrap();
+        backend.put("/other", b"skip").await.unwrap();
+
+        let mut out = Vec::new();
+        assert_eq!(3, backend.export_ndjson("/app/", &mut out).await.unwrap());
+
+        let records: Vec<Record> = String::from_utf8(out).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let keys: Vec<&str> = records.iter().map(|r| r.key.as_str()).collect();
+        assert_eq!(vec!["/app/a", "/app/b", "/app/c"], keys);
+        let decode = |record: &Record| base64::Engine::decode(&base64::engine::general_purpose::STANDARD, record.value.as_ref().unwrap()).unwrap();
+        assert_eq!(b"alpha".to_vec(), decode(&records[0]));
+        assert_eq!(Some("application/json"), records[1].content_type.as_deref());
+        assert_eq!(vec![0, 159, 255], decode(&records[2]));
+        assert_eq!(Some(3), records[2].mod_revision);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn import_ndjson() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let other_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let source = get_backend(&temp_dir).await;
+        let target = get_backend(&other_dir).await;
+
+        source.put("/app/a", b"alpha").await.unwrap();
+        source.put_with_meta("/app/b", b"{}", "application/json").await.unwrap();
+        source.put_tagged("/app/c", "v1", &[0, 159, 255]).await.unwrap();
+        target.put("/app/a", b"old").await.unwrap();
+
+        let mut out = Vec::new();
+        source.export_ndjson("/app/", &mut out).await.unwrap();
+        assert_eq!(3, target.import_ndjson(&out[..]).await.unwrap());
+
+        let kvs = target.list_current("/app/", 0, false).await.unwrap();
+        let imported: Vec<(&str, &[u8])> = kvs.iter().map(|kv| (kv.key().as_str(), kv.value().as_deref().unwrap())).collect();
+        assert_eq!(vec![("/app/a", &b"alpha"[..]), ("/app/b", &b"{}"[..]), ("/app/c", &[0, 159, 255][..])], imported);
+        assert_eq!(Some("application/json"), kvs[1].content_type().as_deref());
+        assert_eq!(Some("v1"), kvs[2].tag().as_deref());
+
+        let input = "{\"key\": \"/bad/a\", \"value\": \"YQ==\"}\n\n{\"key\": \"/bad/b\", \"value\": \"not base64!\"}\n";
+        let err = target.import_ndjson(input.as_bytes()).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidRecord { line: 3, .. }));
+        assert!(target.get("/bad/a", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn on_change() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let backend = get_backend(&temp_dir).await
+            .on_change(Arc::new(move |event: ChangeEvent| sink.lock().unwrap().push(event)));
+
+        let put = backend.put("/svc/a", b"1").await.unwrap();
+        let deleted = backend.delete("/svc/a").await.unwrap();
+        backend.delete("/svc/missing").await.unwrap();
+
+        let seen: Vec<(ChangeOp, String, Revision)> = seen.lock().unwrap().iter()
+            .map(|e| (*e.op(), e.name().clone(), *e.revision()))
+            .collect();
+        assert_eq!(vec![(ChangeOp::Put, "/svc/a".to_string(), put), (ChangeOp::Delete, "/svc/a".to_string(), deleted)], seen);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn count_by_top_level() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a/one", b"1").await.unwrap();
+        backend.put("/a/two", b"1").await.unwrap();
+        backend.put("/b/three", b"1").await.unwrap();
+        backend.put("/c/gone", b"1").await.unwrap();
+        backend.delete("/c/gone").await.unwrap();
+        backend.prune_log(1.into()).await.unwrap();
+
+        let counts = backend.count_by_top_level().await.unwrap();
+        assert_eq!(vec![("a".to_string(), 2), ("b".to_string(), 1)], counts);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn create_revision_matches_mod_revision() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        // Drop the newest row so AUTOINCREMENT runs ahead of MAX(id).
+        backend.put("/gap", b"1").await.unwrap();
+        sqlx::query("DELETE FROM sumkin WHERE name = '/gap'").execute(&backend.pool).await.unwrap();
+
+        let writers: Vec<_> = (0..16).map(|i| {
+            let backend = backend.clone();
+            tokio::spawn(async move {
+                let name = format!("/new/{}", i);
+                retry_unavailable(|| backend.put(&name, b"1")).await
+            })
+        }).collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let guard = backend.try_lock("/new/lock", 30).await.unwrap().unwrap();
+
+        let kvs = backend.list_current("/new/", 0, false).await.unwrap();
+        assert_eq!(17, kvs.len());
+        for kv in kvs {
+            assert_eq!(kv.create_revision(), kv.mod_revision(), "{}", kv.key());
+        }
+        guard.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn check_invariants() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/a", b"2").await.unwrap();
+        backend.put("/b", b"1").await.unwrap();
+        backend.delete("/a").await.unwrap();
+        backend.put("/a", b"3").await.unwrap();
+        assert!(backend.check_invariants().await.unwrap().is_empty());
+
+        sqlx::query(r###"
+            INSERT INTO sumkin(id, name, created, deleted, create_revision, prev_revision) VALUES
+                (10, '/bad/create', 1, 0, 9, NULL),
+                (11, '/bad/tombstone', 0, 1, 0, NULL),
+                (12, '/b', 0, 0, 3, 12)
+        "###).execute(&backend.pool).await.unwrap();
+        let violations = backend.check_invariants().await.unwrap();
+        assert_eq!(vec![
+            InvariantViolation::CreateRevisionMismatch { name: "/bad/create".to_string(), revision: 10.into(), create_revision: 9.into() },
+            InvariantViolation::OrphanTombstone { name: "/bad/tombstone".to_string(), revision: 11.into() },
+            InvariantViolation::NonMonotonicPrevRevision { name: "/b".to_string(), revision: 12.into(), prev_revision: 12.into() },
+        ], violations);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_limits() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().max_list_limit(10);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        let entries: Vec<String> = (0..25).map(|i| format!("/many/{:02}", i)).collect();
+        let entries: Vec<(&str, &[u8])> = entries.iter().map(|name| (name.as_str(), &b"1"[..])).collect();
+        backend.put_batch(&entries, false).await.unwrap();
+
+        assert_eq!(10, backend.list_current("/many/", -1, false).await.unwrap().len());
+        assert_eq!(10, backend.list_current("/many/", 100, false).await.unwrap().len());
+        assert_eq!(5, backend.list_current("/many/", 5, false).await.unwrap().len());
+        assert_eq!(10, backend.range_keys(b"/many/", &prefix_range_end(b"/many/"), 0).await.unwrap().len());
+        assert_eq!(10, backend.recent(-1).await.unwrap().len());
+        assert_eq!(10, backend.recent_under("/many/", -1).await.unwrap().len());
+        assert_eq!(10, backend.list_numeric_sorted("/many/", -1).await.unwrap().len());
+        assert_eq!(10, backend.list_matching("/many/%", -1).await.unwrap().len());
+        assert_eq!(10, backend.list_prefixes(&["/many/"], -1).await.unwrap()["/many/"].len());
+        assert_eq!(10, backend.snapshot(None).await.unwrap().next_page(-1).await.unwrap().len());
+        assert!(logs_contain("Clamping list limit"));
+
+        let config = SqliteConfig::new().default_list_limit(3).max_list_limit(10);
+        assert_eq!(3, config.list_limit(0));
+        assert_eq!(0, config.unbounded().list_limit(-1));
+        assert_eq!(0, SqliteConfig::new().list_limit(-1));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn batch_transaction() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let mut batch = backend.begin().await.unwrap();
+        batch.put("/batch/a", b"1").await.unwrap();
+        batch.put("/batch/b", b"2").await.unwrap();
+        assert_eq!(2, batch.list_current("/batch/", 0, false).await.unwrap().len());
+        batch.rollback().await.unwrap();
+        assert_eq!(0, backend.count("/batch/").await.unwrap());
+        assert_eq!(0, backend.current_revision().await.unwrap());
+
+        let mut batch = backend.begin().await.unwrap();
+        batch.put("/batch/a", b"1").await.unwrap();
+        drop(batch);
+        assert_eq!(0, backend.count("/batch/").await.unwrap());
+
+        let mut batch = backend.begin().await.unwrap();
+        batch.put("/batch/a", b"1").await.unwrap();
+        let revision = batch.put("/batch/b", b"2").await.unwrap();
+        batch.delete("/batch/a").await.unwrap();
+        batch.commit().await.unwrap();
+        let kvs = backend.list_current("/batch/", 0, false).await.unwrap();
+        assert_eq!(1, kvs.len());
+        assert_eq!(revision, *kvs[0].mod_revision());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn from_uri() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+
+        let backend = SqliteBackend::from_uri(&format!("file:{}?cache=private", datasource), SqlitePoolOptions::default()).await.unwrap();
+        backend.put("/uri", b"1").await.unwrap();
+        backend.pool.close().await;
+
+        let readonly = SqliteBackend::from_uri(&format!("file:{}?mode=ro", datasource), SqlitePoolOptions::default()).await.unwrap();
+        assert_eq!(1, readonly.count("/uri").await.unwrap());
+        assert!(readonly.put("/uri", b"2").await.is_err());
+
+        let missing = temp_dir.path().join("missing.db");
+        assert!(SqliteBackend::from_uri(&format!("file:{}?mode=rw", missing.display()), SqlitePoolOptions::default()).await.is_err());
+        assert!(!missing.exists());
+
+        let memory = SqliteBackend::from_uri("sqlite::memory:", SqlitePoolOptions::new().max_connections(1)).await.unwrap();
+        memory.put("/mem", b"1").await.unwrap();
+        assert_eq!(1, memory.count("/mem").await.unwrap());
+
+        assert_eq!(Some("ro"), uri_mode("file:a.db?cache=shared&mode=ro"));
+        assert_eq!(None, uri_mode("file:a.db"));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn put_idempotent() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let first = backend.put_idempotent("/orders/1", b"new", "req-1").await.unwrap();
+        let retried = backend.put_idempotent("/orders/1", b"new", "req-1").await.unwrap();
+        assert_eq!(first, retried);
+        assert_eq!(first, backend.current_revision().await.unwrap());
+
+        let second = backend.put_idempotent("/orders/1", b"paid", "req-2").await.unwrap();
+        assert_eq!(first.next(), second);
+        let kv = backend.get("/orders/1", None).await.unwrap().unwrap();
+        assert_eq!(b"paid", kv.value().as_deref().unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn watch_sse() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/app/a", b"1").await.unwrap();
+        backend.put("/other", b"x").await.unwrap();
+        backend.delete("/app/a").await.unwrap();
+
+        let frames = backend.watch_sse("/app/", 1.into());
+        futures_util::pin_mut!(frames);
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), frames.next()).await.unwrap().unwrap().unwrap();
+            let frame = std::str::from_utf8(&frame).unwrap();
+            let json = frame.strip_prefix("data: ").unwrap().strip_suffix("\n\n").unwrap();
+            events.push(serde_json::from_str::<serde_json::Value>(json).unwrap());
+        }
+
+        assert_eq!("put", events[0]["type"]);
+        assert_eq!("/app/a", events[0]["key"]);
+        assert_eq!("MQ==", events[0]["value"]);
+        assert_eq!(1, events[0]["mod_revision"]);
+        assert_eq!("delete", events[1]["type"]);
+        assert_eq!(3, events[1]["mod_revision"]);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn cached_backend() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let cached = CachedBackend::new(get_backend(&temp_dir).await, std::num::NonZeroUsize::new(2).unwrap());
+        let lookups = || {
+            let count = std::cell::Cell::new(0);
+            logs_assert(|lines: &[&str]| {
+                count.set(lines.iter().filter(|line| line.contains("LIST SQL")).count());
+                Ok(())
+            });
+            count.get()
+        };
+
+        cached.put("/hot", b"1").await.unwrap();
+        assert_eq!(b"1", cached.get("/hot", None).await.unwrap().unwrap().value().as_deref().unwrap());
+        let before = lookups();
+        assert_eq!(b"1", cached.get("/hot", None).await.unwrap().unwrap().value().as_deref().unwrap());
+        assert_eq!(before, lookups());
+
+        cached.put("/hot", b"2").await.unwrap();
+        let before = lookups();
+        assert_eq!(b"2", cached.get("/hot", None).await.unwrap().unwrap().value().as_deref().unwrap());
+        assert_eq!(before + 1, lookups());
+
+        // A write behind the cache's back bumps the revision, so the entry is not served.
+        cached.inner().put("/hot", b"3").await.unwrap();
+        assert_eq!(b"3", cached.get("/hot", None).await.unwrap().unwrap().value().as_deref().unwrap());
+
+        cached.delete("/hot").await.unwrap();
+        assert!(cached.get("/hot", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn case_insensitive_keys() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().case_insensitive_keys(true);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        let created = backend.put("/user/alice", b"1").await.unwrap();
+        let kv = backend.get("/User/ALICE", None).await.unwrap().unwrap();
+        assert_eq!("/user/alice", kv.key());
+
+        backend.put("/USER/Alice", b"2").await.unwrap();
+        let kv = backend.get("/user/alice", None).await.unwrap().unwrap();
+        assert_eq!(b"2", kv.value().as_deref().unwrap());
+        assert_eq!(created, *kv.create_revision());
+        assert_eq!(1, backend.count("/User/").await.unwrap());
+        assert_eq!(1, backend.list_current("/USER/", 0, false).await.unwrap().len());
+        assert_eq!(1, backend.get_map(&["/User/Alice"]).await.unwrap().len());
+        assert_eq!(1, backend.range_keys(b"/User/", &prefix_range_end(b"/User/"), 0).await.unwrap().len());
+
+        backend.delete("/User/Alice").await.unwrap();
+        assert!(backend.get("/user/alice", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn case_sensitive_keys() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/user/alice", b"1").await.unwrap();
+        assert!(backend.get("/User/Alice", None).await.unwrap().is_none());
+        assert_eq!(0, backend.count("/User/").await.unwrap());
+        assert!(backend.list_current("/USER/", 0, false).await.unwrap().is_empty());
+
+        backend.put("/User/Alice", b"2").await.unwrap();
+        assert_eq!(2, backend.count("/").await.unwrap());
+        assert_eq!(1, backend.count("/user/").await.unwrap());
+        assert_eq!(b"1", backend.get("/user/alice", None).await.unwrap().unwrap().value().as_deref().unwrap());
+        assert_eq!(b"2", backend.get("/User/Alice", None).await.unwrap().unwrap().value().as_deref().unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn case_insensitive_keys_reopen() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().case_insensitive_keys(true);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config.clone()).await.unwrap();
+        backend.put("/user/alice", b"1").await.unwrap();
+        backend.close().await;
+
+        let reopened = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+        let kv = reopened.get("/User/Alice", None).await.unwrap().unwrap();
+        assert_eq!(b"1", kv.value().as_deref().unwrap());
+        reopened.put("/USER/ALICE", b"2").await.unwrap();
+        assert_eq!(1, reopened.count("/user/").await.unwrap());
+        reopened.close().await;
+
+        let binary_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let binary = get_random_datasource(&binary_dir);
+        let backend = SqliteBackend::new(Path::new(binary.as_str()), SqlitePoolOptions::default()).await.unwrap();
+        backend.put("/user/alice", b"1").await.unwrap();
+        backend.close().await;
+        let config = SqliteConfig::new().case_insensitive_keys(true);
+        let converted = SqliteBackend::with_config(Path::new(binary.as_str()), SqlitePoolOptions::default(), config).await;
+        assert!(matches!(converted, Err(Error::SchemaMismatch { .. })));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn log_count() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/b", b"1").await.unwrap();
+        backend.put("/c", b"1").await.unwrap();
+        backend.put("/a", b"2").await.unwrap();
+        backend.delete("/b").await.unwrap();
+
+        assert_eq!(5, backend.log_count().await.unwrap());
+        assert_eq!(2, backend.count("/").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn get_range_bytes() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let value: Vec<u8> = (0..100).collect();
+        backend.put("/blob", &value).await.unwrap();
+
+        let range = backend.get_range_bytes("/blob", 10, 10).await.unwrap();
+        assert_eq!(Some(value[10..20].to_vec()), range);
+        let tail = backend.get_range_bytes("/blob", 95, 10).await.unwrap();
+        assert_eq!(Some(value[95..].to_vec()), tail);
+        let past_end = backend.get_range_bytes("/blob", 200, 10).await.unwrap();
+        assert_eq!(Some(Vec::new()), past_end);
+        assert!(backend.get_range_bytes("/missing", 0, 10).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn append() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let created = backend.append("/log", b"one,").await.unwrap();
+        let appended = backend.append("/log", b"two").await.unwrap();
+        assert!(appended > created);
+
+        let kv = backend.get("/log", None).await.unwrap().unwrap();
+        assert_eq!(Some(b"one,two".to_vec()), *kv.value());
+        assert_eq!(created, *kv.create_revision());
+        assert_eq!(appended, *kv.mod_revision());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn delete_checked() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let put = backend.put("/a", b"1").await.unwrap();
+        let (deleted, revision) = backend.delete_checked("/a").await.unwrap();
+        assert!(deleted);
+        assert_eq!(put.next(), revision);
+        assert!(backend.get("/a", None).await.unwrap().is_none());
+
+        let (deleted, noop) = backend.delete_checked("/a").await.unwrap();
+        assert!(!deleted);
+        assert_eq!(revision, noop);
+        let (deleted, noop) = backend.delete_checked("/missing").await.unwrap();
+        assert!(!deleted);
+        assert_eq!(revision, noop);
+        assert_eq!(revision, backend.current_revision().await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn config_warm_up() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().warm_up(4);
+        let pool_opts = SqlitePoolOptions::new().max_connections(4);
+
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), pool_opts, config).await.unwrap();
+
+        assert_eq!(4, backend.pool.size());
+        backend.put("/a", b"1").await.unwrap();
+        assert_eq!(4, backend.pool.size());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn created_since() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/root/old", b"1").await.unwrap();
+        let checkpoint = backend.current_revision().await.unwrap();
+        backend.put("/root/new", b"1").await.unwrap();
+        backend.put("/root/old", b"2").await.unwrap();
+
+        let kvs = backend.created_since("/root/", checkpoint).await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/root/new"], keys);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_numeric_sorted() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/item/10", b"1").await.unwrap();
+        backend.put("/item/9", b"1").await.unwrap();
+        backend.put("/item/100", b"1").await.unwrap();
+
+        let kvs = backend.list_numeric_sorted("/item/", -1).await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/item/9", "/item/10", "/item/100"], keys);
+
+        let kvs = backend.list_numeric_sorted("/item/", 2).await.unwrap();
+        assert_eq!(2, kvs.len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn closed() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a", b"1").await.unwrap();
+        backend.close().await;
+
+        assert!(matches!(backend.put("/a", b"2").await, Err(Error::Closed)));
+        assert!(matches!(backend.get("/a", None).await, Err(Error::Closed)));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn blob_threshold() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().blob_threshold(64);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        let large = vec![7u8; 1000];
+        backend.put("/blob/a", &large).await.unwrap();
+        backend.put("/blob/b", &large).await.unwrap();
+        backend.put("/blob/small", b"small").await.unwrap();
+
+        let blobs: i64 = sqlx::query("SELECT COUNT(*) FROM sumkin_blobs").fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!(1, blobs);
+        let inline: i64 = sqlx::query("SELECT COUNT(*) FROM sumkin WHERE value IS NOT NULL").fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!(1, inline);
+
+        for name in ["/blob/a", "/blob/b"] {
+            let kv = backend.get(name, None).await.unwrap().unwrap();
+            assert_eq!(Some(large.clone()), *kv.value());
+        }
+        assert_eq!(1000, *backend.stat("/blob/a").await.unwrap().unwrap().value_size());
+        assert_eq!(Some(vec![7u8; 4]), backend.get_range_bytes("/blob/b", 10, 4).await.unwrap());
+        assert!(backend.list_empty_values("/blob/").await.unwrap().is_empty());
+
+        backend.delete("/blob/a").await.unwrap();
+        backend.delete("/blob/b").await.unwrap();
+        let revision = backend.current_revision().await.unwrap();
+        backend.prune_log(revision).await.unwrap();
+        let blobs: i64 = sqlx::query("SELECT COUNT(*) FROM sumkin_blobs").fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!(0, blobs);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn transform_values() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for i in 0..(TRANSFORM_BATCH_SIZE + 5) {
+            backend.put(&format!("/app/{:03}", i), b"value").await.unwrap();
+        }
+        backend.put_tagged("/app/tagged", "v1", b"tagged").await.unwrap();
+        backend.put("/other", b"other").await.unwrap();
+
+        let transformed = backend.transform_values("/app/", |value| value.to_ascii_uppercase()).await.unwrap();
+        assert_eq!(TRANSFORM_BATCH_SIZE as u64 + 6, transformed);
+
+        let kvs = backend.list_current("/app/", 0, false).await.unwrap();
+        assert_eq!(TRANSFORM_BATCH_SIZE as usize + 6, kvs.len());
+        assert!(kvs.iter().all(|kv| kv.value().as_deref() == Some(&b"VALUE"[..]) || kv.key() == "/app/tagged"));
+        let tagged = backend.get("/app/tagged", None).await.unwrap().unwrap();
+        assert_eq!(Some(b"TAGGED".to_vec()), *tagged.value());
+        assert_eq!(Some("v1"), tagged.tag().as_deref());
+        let other = backend.get("/other", None).await.unwrap().unwrap();
+        assert_eq!(Some(b"other".to_vec()), *other.value());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn compact_prefix() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for i in 0..3 {
+            backend.put("/a/x", format!("{}", i).as_bytes()).await.unwrap();
+            backend.put("/b/x", format!("{}", i).as_bytes()).await.unwrap();
+        }
+        backend.put("/a/gone", b"1").await.unwrap();
+        backend.delete("/a/gone").await.unwrap();
+        let revision = backend.current_revision().await.unwrap();
+
+        assert_eq!(4, backend.compact_prefix("/a/", revision).await.unwrap());
+
+        let a_rows: i64 = sqlx::query("SELECT COUNT(*) FROM sumkin WHERE name LIKE '/a/%'").fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!(1, a_rows);
+        let b_rows: i64 = sqlx::query("SELECT COUNT(*) FROM sumkin WHERE name LIKE '/b/%'").fetch_one(&backend.pool).await.unwrap().get(0);
+        assert_eq!(3, b_rows);
+        assert_eq!(Some(b"2".to_vec()), *backend.get("/a/x", None).await.unwrap().unwrap().value());
+        assert_eq!(0, backend.compact_revision().await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn free_page_ratio() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let value = vec![1u8; 4096];
+        for i in 0..100 {
+            backend.put(&format!("/bulk/{}", i), &value).await.unwrap();
+        }
+        backend.delete_range_prev("/bulk/").await.unwrap();
+        let revision = backend.current_revision().await.unwrap();
+        backend.prune_log(revision).await.unwrap();
+
+        assert!(backend.free_page_ratio().await.unwrap() > 0.5);
+        sqlx::query("VACUUM").execute(&backend.pool).await.unwrap();
+        assert!(backend.free_page_ratio().await.unwrap() < 0.01);
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn get_consistent() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let token = backend.put("/a", b"1").await.unwrap();
+        let kv = backend.get_consistent("/a", token).await.unwrap().unwrap();
+        assert_eq!(token, *kv.mod_revision());
+
+        let writer = backend.clone();
+        let write = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            writer.put("/a", b"2").await.unwrap()
+        });
+        let kv = backend.get_consistent("/a", token.next()).await.unwrap().unwrap();
+        assert_eq!(Some(b"2".to_vec()), *kv.value());
+        assert_eq!(write.await.unwrap(), *kv.mod_revision());
+
+        let other_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&other_dir);
+        let config = SqliteConfig::new().consistent_read_timeout(Duration::from_millis(100));
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+        let token = backend.put("/a", b"1").await.unwrap();
+        let err = backend.get_consistent("/a", token.next()).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { after } if after == Duration::from_millis(100)));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn active_leases() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let first = backend.grant_lease(30).await.unwrap();
+        let second = backend.grant_lease(30).await.unwrap();
+        let unused = backend.grant_lease(30).await.unwrap();
+        for (name, lease) in [("/a", Some(first)), ("/b", Some(second)), ("/c", Some(second)), ("/d", None)] {
+            backend.put(name, b"1").await.unwrap();
+            backend.attach_lease(name, lease).await.unwrap();
+        }
+        backend.put("/gone", b"1").await.unwrap();
+        backend.attach_lease("/gone", Some(unused)).await.unwrap();
+        backend.delete("/gone").await.unwrap();
+
+        assert_eq!(vec![first, second], backend.active_leases().await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn touch() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let clock = MockClock::new(5_000);
+        let backend = get_backend(&temp_dir).await.with_clock(Arc::new(clock.clone()));
+
+        let created = backend.put_tagged("/a", "v1", b"1").await.unwrap();
+        clock.advance(1_000);
+        let touched = backend.touch("/a").await.unwrap();
+        assert!(touched > created);
+
+        let kv = backend.get("/a", None).await.unwrap().unwrap();
+        assert_eq!(touched, *kv.mod_revision());
+        assert_eq!(created, *kv.create_revision());
+        assert_eq!(Some(b"1".to_vec()), *kv.value());
+        assert_eq!(Some("v1"), kv.tag().as_deref());
+        assert_eq!(Some(6_000), *kv.mtime());
+
+        let err = backend.touch("/missing").await.unwrap_err();
+        assert!(matches!(err, Error::KeyNotFound { .. }));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn changes_since() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/app/quiet", b"1").await.unwrap();
+        backend.put("/app/gone", b"1").await.unwrap();
+        let checkpoint = backend.current_revision().await.unwrap();
+        backend.put("/app/busy", b"1").await.unwrap();
+        let last = backend.put("/app/busy", b"2").await.unwrap();
+        backend.delete("/app/gone").await.unwrap();
+        backend.put("/other", b"1").await.unwrap();
+
+        let changes = backend.changes_since("/app/", checkpoint).await.unwrap();
+        assert_eq!(2, changes.len());
+        let busy = &changes["/app/busy"];
+        assert_eq!(last, *busy.mod_revision());
+        assert_eq!(Some(b"2".to_vec()), *busy.value());
+        assert!(*changes["/app/gone"].deleted());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_shard() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        for i in 0..50 {
+            backend.put(&format!("/jobs/{}", i), b"1").await.unwrap();
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for shard in 0..4 {
+            for kv in backend.list_shard("/jobs/", shard, 4).await.unwrap() {
+                assert!(seen.insert(kv.key().clone()), "{} is in two shards", kv.key());
+            }
+        }
+        let all: std::collections::HashSet<String> = backend.list_current("/jobs/", 0, false).await.unwrap()
+            .into_iter().map(|kv| kv.key().clone()).collect();
+        assert_eq!(all, seen);
+
+        let revisions: Vec<Revision> = backend.list_shard("/jobs/", 1, 4).await.unwrap().iter().map(|kv| *kv.mod_revision()).collect();
+        assert!(revisions.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(Some(b"1".to_vec()), *backend.list_shard("/jobs/", 1, 4).await.unwrap()[0].value());
+        assert!(matches!(backend.list_shard("/jobs/", 0, 0).await, Err(Error::InvalidArgument { .. })));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn clear_bumps_generation() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        assert_eq!(0, backend.generation().await.unwrap());
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/b", b"1").await.unwrap();
+
+        backend.clear().await.unwrap();
+        assert_eq!(1, backend.generation().await.unwrap());
+        assert!(backend.is_empty().await.unwrap());
+        assert_eq!(0, backend.log_count().await.unwrap());
+        assert_eq!(1, backend.put("/a", b"2").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn list_prefixes() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a/1", b"1").await.unwrap();
+        backend.put("/a/2", b"1").await.unwrap();
+        backend.put("/b/1", b"1").await.unwrap();
+        backend.put("/c/1", b"1").await.unwrap();
+
+        let listings = backend.list_prefixes(&["/a/", "/b/", "/empty/"], 0).await.unwrap();
+        assert_eq!(3, listings.len());
+        let keys = |prefix: &str| listings[prefix].iter().map(|kv| kv.key().as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["/a/1", "/a/2"], keys("/a/"));
+        assert_eq!(vec!["/b/1"], keys("/b/"));
+        assert!(keys("/empty/").is_empty());
+
+        let limited = backend.list_prefixes(&["/a/"], 1).await.unwrap();
+        assert_eq!(1, limited["/a/"].len());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn revision_seq() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().revision_seq(true);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        assert_eq!(1, backend.put("/a", b"1").await.unwrap());
+        assert_eq!(2, backend.put("/a", b"2").await.unwrap());
+        assert_eq!(3, backend.delete("/a").await.unwrap());
+
+        backend.clear().await.unwrap();
+        assert_eq!(4, backend.put("/a", b"3").await.unwrap());
+        let guard = backend.try_lock("/lock", 30).await.unwrap().unwrap();
+        assert_eq!(5, guard.revision());
+        assert!(backend.try_lock("/lock", 30).await.unwrap().is_none());
+        assert_eq!(6, backend.put("/b", b"1").await.unwrap());
+        assert!(backend.check_invariants().await.unwrap().is_empty());
+        guard.release().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn write_stats() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a", b"12345").await.unwrap();
+        backend.put("/a", b"123").await.unwrap();
+        backend.put("/b", b"12").await.unwrap();
+        backend.delete("/a").await.unwrap();
+
+        let stats = backend.write_stats().await.unwrap();
+        assert_eq!(4, *stats.rows_written());
+        assert_eq!(2, *stats.creates());
+        assert_eq!(1, *stats.tombstones());
+        assert_eq!(10, *stats.bytes_written());
+        assert_eq!(backend.log_count().await.unwrap() as i64, *stats.rows_written());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn error_codes() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let backend = get_backend(&temp_dir).await;
+
+        let err = backend.attach_lease("/missing", None).await.unwrap_err();
+        assert_eq!(ErrorCode::NotFound, err.code());
+
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/a", b"2").await.unwrap();
+        backend.prune_log(2.into()).await.unwrap();
+        let err = backend.snapshot(Some(1.into())).await.unwrap_err();
+        assert_eq!(ErrorCode::Compacted, err.code());
+
+        let err = backend.import_ndjson(&b"not json\n"[..]).await.unwrap_err();
+        assert_eq!(ErrorCode::Invalid, err.code());
+
+        let readonly = SqliteBackend::from_uri(&format!("file:{}?mode=ro", datasource), SqlitePoolOptions::default()).await.unwrap();
+        let err = readonly.put("/a", b"3").await.unwrap_err();
+        assert_eq!(ErrorCode::ReadOnly, err.code());
+
+        backend.close().await;
+        let err = backend.put("/a", b"3").await.unwrap_err();
+        assert_eq!(ErrorCode::Unavailable, err.code());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn wait_for() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/job", b"pending").await.unwrap();
+        let writer = backend.clone();
+        let write = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            writer.put("/job", b"running").await.unwrap();
+            writer.put("/job", b"done").await.unwrap()
+        });
+
+        let done = |kv: &KeyValue| kv.value().as_deref() == Some(&b"done"[..]);
+        let kv = backend.wait_for("/job", done, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(write.await.unwrap(), *kv.mod_revision());
+
+        let kv = backend.wait_for("/job", done, Duration::from_millis(10)).await.unwrap();
+        assert_eq!(Some(b"done".to_vec()), *kv.value());
+
+        let err = backend.wait_for("/job", |_| false, Duration::from_millis(100)).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout { .. }));
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn sync() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/a", b"1").await.unwrap();
+        backend.sync().await.unwrap();
+        assert!(logs_contain("Checkpointed"));
+        assert_eq!(Some(b"1".to_vec()), *backend.get("/a", None).await.unwrap().unwrap().value());
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn annotations() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        backend.put("/svc/a", b"1").await.unwrap();
+        backend.put("/svc/b", b"1").await.unwrap();
+        backend.put("/svc/c", b"1").await.unwrap();
+        backend.set_annotation("/svc/a", "team", "infra").await.unwrap();
+        backend.set_annotation("/svc/a", "tier", "1").await.unwrap();
+        backend.set_annotation("/svc/b", "team", "infra").await.unwrap();
+        backend.set_annotation("/svc/c", "team", "web").await.unwrap();
+
+        let annotations = backend.get_annotations("/svc/a").await.unwrap();
+        assert_eq!(2, annotations.len());
+        assert_eq!("infra", annotations["team"]);
+        assert_eq!("1", annotations["tier"]);
+
+        let kvs = backend.list_annotated("team", "infra").await.unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key().as_str()).collect();
+        assert_eq!(vec!["/svc/a", "/svc/b"], keys);
 
-        let options = SqliteConnectOptions::new()
-            .filename(filepath)
-            .journal_mode(SqliteJournalMode::Wal)
-            .shared_cache(true);
-        let pool = pool_options.connect_with(options).await?;
+        backend.put("/svc/b", b"2").await.unwrap();
+        assert!(backend.get_annotations("/svc/b").await.unwrap().is_empty());
+        assert_eq!(1, backend.list_annotated("team", "infra").await.unwrap().len());
 
-        debug!("Connecting to datasource: {}", &filepath.display());
-        Self::with_pool(pool).await
+        let err = backend.set_annotation("/svc/missing", "team", "infra").await.unwrap_err();
+        assert!(matches!(err, Error::KeyNotFound { .. }));
     }
 
-    pub async fn with_pool(pool: SqlitePool) -> SumkinResult<Self> {
-        info!("Configuring database table schema and indexes, this may take a moment...");
+    #[tokio::test]
+    #[traced_test]
+    async fn floor_and_ceiling() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
-        for migration in SCHEMA {
-            debug!("Running migration : {}", migration);
-            pool.execute(*migration).await?;
+        for name in ["/a", "/c", "/e", "/f"] {
+            backend.put(name, b"1").await.unwrap();
         }
-        info!("Backend setup complete.");
-        Ok(Self {
-            pool
-        })
+        backend.delete("/f").await.unwrap();
 
+        let key = |kv: Option<KeyValue>| kv.map(|kv| kv.key().clone());
+        assert_eq!(Some("/c".to_owned()), key(backend.floor("/d").await.unwrap()));
+        assert_eq!(Some("/e".to_owned()), key(backend.ceiling("/d").await.unwrap()));
+        assert_eq!(Some("/c".to_owned()), key(backend.floor("/c").await.unwrap()));
+        assert_eq!(Some("/c".to_owned()), key(backend.ceiling("/c").await.unwrap()));
+        assert_eq!(None, key(backend.floor("/0").await.unwrap()));
+        assert_eq!(None, key(backend.ceiling("/f").await.unwrap()));
+        assert_eq!(Some("/e".to_owned()), key(backend.floor("/z").await.unwrap()));
     }
 
-    async fn get_with_tx(tx: &mut Transaction<'_, Sqlite>, name: &str, revision: Option<Revision>) -> SumkinResult<Option<KeyValue>> {
-        if let Some(_r) = revision {
-            unimplemented!();
-        } else {
-            let kv = Self::list_current_with_tx(tx, name, 1, false).await?;
-            Ok(kv.into_iter().next())
-        }
+    #[tokio::test]
+    #[traced_test]
+    async fn hard_delete() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().hard_delete(true);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
 
-    }
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/a", b"2").await.unwrap();
+        let kept = backend.put("/b", b"1").await.unwrap();
+        assert_eq!(3, backend.log_count().await.unwrap());
 
-    async fn list_current_with_tx(tx: &mut Transaction<'_, Sqlite>, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
-        let sql = if limit > 0 {
-            format!("{} LIMIT {}", sql::GET_CURRENT_SQL.as_str(), limit)
-        } else {
-            sql::GET_CURRENT_SQL.clone()
-        };
+        let (deleted, revision) = backend.delete_checked("/a").await.unwrap();
+        assert!(deleted);
+        assert_eq!(kept.next(), revision);
+        assert_eq!(1, backend.log_count().await.unwrap());
+        assert!(backend.get("/a", None).await.unwrap().is_none());
 
-        debug!("LIST SQL: {}", &sql);
+        backend.put("/c/1", b"1").await.unwrap();
+        let removed = backend.delete_range_prev("/c/").await.unwrap();
+        assert_eq!(1, removed.len());
+        assert_eq!(1, backend.log_count().await.unwrap());
 
-        let rows = if prefix.ends_with('/') {
-            let prefix = format!("{}%", prefix);
-            sqlx::query_as::<_, KeyValue>(&sql)
-                .bind(&prefix)
-                .bind(include_deleted)
-                .fetch_all(tx).await?
-        } else {
-            sqlx::query_as::<_, KeyValue>(&sql)
-                .bind(prefix)
-                .bind(include_deleted)
-                .fetch_all(tx).await?
-        };
-        Ok(rows)
+        // Removing the newest rows must not hand out a revision that was already used.
+        let newest = backend.put("/d", b"1").await.unwrap();
+        let (_, revision) = backend.delete_checked("/d").await.unwrap();
+        assert_eq!(newest.next(), revision);
+        assert!(backend.put("/d", b"2").await.unwrap() > revision);
     }
 
-    async fn insert_with_tx(tx: &mut Transaction<'_, Sqlite>, name: &str, created: bool, deleted: bool, create_revision: Revision, prev_revision: Option<Revision>, lease: Option<i64>, value: Option<&[u8]>, old_value: Option<Vec<u8>>) -> SumkinResult<Revision> {
-        debug!("INSERT SQL: {}", sql::INSERT);
-        let row = sqlx::query(sql::INSERT)
-            .bind(name)
-            .bind(created)
-            .bind(deleted)
-            .bind(create_revision)
-            .bind(prev_revision)
-            .bind(lease)
-            .bind(value)
-            .bind(old_value)
-            .execute(tx).await?;
-        Ok(row.last_insert_rowid())
-    }
+    #[tokio::test]
+    #[traced_test]
+    async fn changed_since() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
+        backend.put("/a", b"1").await.unwrap();
+        let token = backend.current_revision().await.unwrap();
+        assert!(!backend.changed_since(token).await.unwrap());
 
-    async fn current_revision_with_tx(tx: &mut Transaction<'_, Sqlite>) -> SumkinResult<Revision> {
-        debug!("CURRENT REVISION SQL: {}", sql::CURRENT_REVISION_SQL);
-        let size: i64 = sqlx::query(sql::CURRENT_REVISION_SQL).fetch_one(tx).await?.try_get("id")?;
-        Ok(size)
+        backend.delete("/a").await.unwrap();
+        assert!(backend.changed_since(token).await.unwrap());
     }
-}
 
-#[async_trait]
-impl Backend for SqliteBackend {
-    async fn size(&self) -> SumkinResult<u64> {
-        debug!("SIZE SQL: {}", sql::SIZE_SQL);
-        let size: i64 = sqlx::query(sql::SIZE_SQL).fetch_one(&self.pool).await?.try_get(0)?;
-        Ok(size as u64)
+    #[tokio::test]
+    #[traced_test]
+    async fn prefix_mode() {
+        let keys = |kvs: Vec<KeyValue>| kvs.into_iter().map(|kv| kv.key().clone()).collect::<Vec<_>>();
+
+        for (mode, expected) in [(PrefixMode::Exact, "/root"), (PrefixMode::Subtree, "/root/child")] {
+            let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+            let datasource = get_random_datasource(&temp_dir);
+            let config = SqliteConfig::new().prefix_mode(mode);
+            let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+            backend.put("/root", b"parent").await.unwrap();
+            backend.put("/root/child", b"child").await.unwrap();
+
+            assert_eq!(vec![expected], keys(backend.list_current("/root", 0, false).await.unwrap()));
+            assert_eq!(1, backend.count("/root").await.unwrap());
+            assert_eq!(vec!["/root/child"], keys(backend.list_current("/root/", 0, false).await.unwrap()));
+            assert_eq!(1, backend.count("/root/").await.unwrap());
+
+            assert_eq!(Some(b"parent".to_vec()), *backend.get("/root", None).await.unwrap().unwrap().value());
+        }
     }
 
-    async fn current_revision(&self) -> SumkinResult<Revision> {
-        debug!("CURRENT REVISION SQL: {}", sql::CURRENT_REVISION_SQL);
-        let size: i64 = sqlx::query(sql::CURRENT_REVISION_SQL).fetch_one(&self.pool).await?.try_get("id")?;
-        Ok(size)
+    #[tokio::test]
+    #[traced_test]
+    async fn subtree_root_skips_compaction_marker() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().prefix_mode(PrefixMode::Subtree);
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/b", b"2").await.unwrap();
+        backend.delete("/b").await.unwrap();
+        backend.prune_log(backend.current_revision().await.unwrap()).await.unwrap();
+
+        let keys: Vec<String> = backend.list_current("", 0, false).await.unwrap().into_iter().map(|kv| kv.key().clone()).collect();
+        assert_eq!(vec!["/a"], keys);
+        assert_eq!(1, backend.count("").await.unwrap());
+        assert_eq!((1, 0), backend.count_all("").await.unwrap());
+
+        let mut out = Vec::new();
+        assert_eq!(1, backend.export_ndjson("", &mut out).await.unwrap());
+        assert!(!String::from_utf8(out).unwrap().contains("compact_rev_key"));
     }
 
-    async fn count(&self, prefix: &str) -> SumkinResult<u64> {
-        debug!("COUNT SQL: {}", sql::COUNT_SQL.as_str());
-        let row = if prefix.ends_with('/') {
-            let prefix = format!("{}%", prefix);
-            sqlx::query(sql::COUNT_SQL.as_str()).bind(&prefix).fetch_one(&self.pool).await?
-        } else {
+    #[tokio::test]
+    #[traced_test]
+    async fn swap() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
-            sqlx::query(sql::COUNT_SQL.as_str()).bind(prefix).fetch_one(&self.pool).await?
-        };
-        let count: i64 = row.try_get("count")?;
-        Ok(count as u64)
+        backend.put("/blue", b"v1").await.unwrap();
+        backend.put("/green", b"v2").await.unwrap();
+
+        let (blue, green) = backend.swap("/blue", "/green").await.unwrap();
+        assert_eq!(3, blue);
+        assert_eq!(4, green);
+        let blue = backend.get("/blue", None).await.unwrap().unwrap();
+        let green = backend.get("/green", None).await.unwrap().unwrap();
+        assert_eq!(Some(b"v2".to_vec()), *blue.value());
+        assert_eq!(Some(b"v1".to_vec()), *green.value());
+        assert_eq!(3, *blue.mod_revision());
+
+        assert!(matches!(backend.swap("/blue", "/missing").await, Err(Error::KeyNotFound { name }) if name == "/missing"));
+        assert_eq!(4, backend.current_revision().await.unwrap());
     }
 
-    async fn put(&self, name: &str, value: &[u8]) -> SumkinResult<Revision> {
-        let mut tx = self.pool.begin().await?;
-        let next_revision = Self::current_revision_with_tx(&mut tx).await? + 1;
-        let revision = if let Some(kv) = Self::get_with_tx(&mut tx, name, None).await? {
-            debug!("Updating existing key: {}", name);
-            Self::insert_with_tx(&mut tx, name, false, false, *kv.create_revision(),  None, None, Some(value), kv.value().clone()).await?
-        } else {
-            debug!("Creating new key: {}", name);
-            Self::insert_with_tx(&mut tx, name, true, false, next_revision, None, None, Some(value), None).await?
-        };
-        tx.commit().await?;
-        Ok(revision)
+    #[tokio::test]
+    #[traced_test]
+    async fn revision_limits() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let config = SqliteConfig::new().revision_warn_threshold(2.into()).max_revision(3.into());
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
+
+        backend.put("/a", b"1").await.unwrap();
+        assert!(!logs_contain("past the warning threshold"));
+        backend.put("/a", b"2").await.unwrap();
+        assert!(logs_contain("Revision 2 is past the warning threshold of 2"));
+        assert_eq!(3, backend.put("/a", b"3").await.unwrap());
+
+        let err = backend.put("/a", b"4").await.unwrap_err();
+        assert!(matches!(err, Error::RevisionLimit { revision, max_revision } if revision == 4 && max_revision == 3));
+        assert!(backend.put("/b", b"1").await.is_err());
+        assert_eq!(3, backend.current_revision().await.unwrap());
+        assert_eq!(Some(b"3".to_vec()), *backend.get("/a", None).await.unwrap().unwrap().value());
     }
 
-    async fn list_current(&self, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>> {
-        let mut tx = self.pool.begin().await?;
-        let kvs = Self::list_current_with_tx(&mut tx, prefix, limit, include_deleted).await?;
-        tx.commit().await?;
+    #[tokio::test]
+    #[traced_test]
+    async fn list_hashes() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
-        Ok(kvs)
+        backend.put("/sync/a", b"same").await.unwrap();
+        backend.put("/sync/b", b"same").await.unwrap();
+        backend.put("/sync/c", b"different").await.unwrap();
+        backend.put("/sync/gone", b"same").await.unwrap();
+        backend.delete("/sync/gone").await.unwrap();
+        backend.put("/other", b"same").await.unwrap();
 
+        let hashes = backend.list_hashes("/sync/").await.unwrap();
+        let names = hashes.iter().map(|(name, _, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(vec!["/sync/a", "/sync/b", "/sync/c"], names);
+        assert_eq!(1, hashes[0].1);
+        assert_eq!(hashes[0].2, hashes[1].2);
+        assert_ne!(hashes[0].2, hashes[2].2);
+        // The standard CRC-32 check value.
+        assert_eq!(0xcbf4_3926, VALUE_CRC.checksum(b"123456789"));
     }
 
-    async fn delete(&self, name: &str) -> SumkinResult<Revision> {
-        let mut tx = self.pool.begin().await?;
-        if let Some(kv) = Self::get_with_tx(&mut tx, name, None).await? {
-            let revision = Self::insert_with_tx(&mut tx, name, false, true, 0,  None, None, None, kv.value().clone()).await?;
-            tx.commit().await?;
-            Ok(revision)
-        } else {
-            self.current_revision().await
+    #[tokio::test]
+    #[traced_test]
+    async fn create_or_get() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+
+        let registrations: Vec<_> = ["node-a", "node-b"].iter().map(|node| {
+            let backend = backend.clone();
+            tokio::spawn(async move { retry_unavailable(|| backend.create_or_get("/leader", node.as_bytes())).await })
+        }).collect();
+        let mut results = Vec::new();
+        for registration in registrations {
+            results.push(registration.await.unwrap());
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!(1, results.iter().filter(|(created, _)| *created).count());
+        assert_eq!(results[0].1.value(), results[1].1.value());
+        assert_eq!(1, *results[1].1.mod_revision());
+        assert_eq!(1, backend.current_revision().await.unwrap());
+        assert_eq!(*results[0].1.value(), *backend.get("/leader", None).await.unwrap().unwrap().value());
 
-    use tracing_test::traced_test;
-    use tempfile::TempDir;
+        backend.delete("/leader").await.unwrap();
+        let (created, kv) = backend.create_or_get("/leader", b"node-c").await.unwrap();
+        assert!(created);
+        assert_eq!(3, *kv.create_revision());
+        assert_eq!(Some(b"node-c".to_vec()), *kv.value());
+    }
 
-    fn get_random_datasource(dir: &tempfile::TempDir) -> String {
-        let path = dir.path().join("state.db");
+    #[tokio::test]
+    #[traced_test]
+    async fn bootstrap_if_empty() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
-        path.to_string_lossy().to_owned().to_string()
+        let bootstraps: Vec<_> = ["node-a", "node-b"].iter().map(|node| {
+            let backend = backend.clone();
+            tokio::spawn(async move {
+                let entries: Vec<(&str, &[u8])> = vec![("/defaults/owner", node.as_bytes()), ("/defaults/replicas", b"3")];
+                retry_unavailable(|| backend.bootstrap_if_empty("/defaults/", &entries)).await
+            })
+        }).collect();
+        let mut seeded = Vec::new();
+        for bootstrap in bootstraps {
+            seeded.push(bootstrap.await.unwrap());
+        }
 
+        assert_eq!(1, seeded.iter().filter(|seeded| **seeded).count());
+        assert_eq!(2, backend.current_revision().await.unwrap());
+        assert_eq!(2, backend.count("/defaults/").await.unwrap());
+        let owner = backend.get("/defaults/owner", None).await.unwrap().unwrap();
+        let winner = if seeded[0] { "node-a" } else { "node-b" };
+        assert_eq!(Some(winner.as_bytes().to_vec()), *owner.value());
     }
 
     #[tokio::test]
     #[traced_test]
-    async fn super_basic() {
+    async fn watch_prev_kv() {
         let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
+        backend.put("/app/config", b"old").await.unwrap();
+        backend.put("/app/config", b"new").await.unwrap();
+        backend.touch("/app/config").await.unwrap();
+        backend.delete("/app/config").await.unwrap();
 
-        let datasource = get_random_datasource(&temp_dir);
-        let pool_opts = SqlitePoolOptions::default();
+        let watch = backend.watch_prefix("/app/", 1.into(), true);
+        let events: Vec<WatchEvent> = watch.take(4).map(|event| event.unwrap()).collect().await;
 
-        let backend = SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap();
+        assert!(events[0].prev_kv().is_none());
+        assert!(matches!(&events[1], WatchEvent::Put { kv, prev_kv: Some(prev_kv) }
+            if *kv.value() == Some(b"new".to_vec()) && *prev_kv.value() == Some(b"old".to_vec()) && *prev_kv.mod_revision() == 1));
+        assert!(matches!(&events[2], WatchEvent::Put { prev_kv: Some(prev_kv), .. } if *prev_kv.mod_revision() == 2));
+        assert!(matches!(&events[3], WatchEvent::Delete { prev_kv: Some(prev_kv), .. } if *prev_kv.mod_revision() == 3));
 
-        let _og_size = backend.size().await.unwrap();
+        let watch = backend.watch_prefix("/app/", 1.into(), false);
+        let events: Vec<WatchEvent> = watch.take(4).map(|event| event.unwrap()).collect().await;
+        assert!(events.iter().all(|event| event.prev_kv().is_none()));
+    }
 
-        let revision = backend.current_revision().await.unwrap();
-        assert_eq!(0, revision);
+    #[tokio::test]
+    #[traced_test]
+    async fn revert() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
+        let first = backend.put("/config", b"v1").await.unwrap();
+        backend.put("/config", b"v2").await.unwrap();
+        backend.put("/config", b"v3").await.unwrap();
 
-        let count = backend.count("/").await.unwrap();
-        assert_eq!(0, count);
+        let revision = backend.revert("/config", first).await.unwrap();
+        assert_eq!(4, revision);
+        let kv = backend.get("/config", None).await.unwrap().unwrap();
+        assert_eq!(Some(b"v1".to_vec()), *kv.value());
+        assert_eq!(revision, *kv.mod_revision());
+        assert_eq!(first, *kv.create_revision());
+        assert_eq!(Some(b"v2".to_vec()), *backend.event_at(2.into()).await.unwrap().unwrap().value());
 
-        let list = backend.list_current("/", -1, false).await.unwrap();
-        assert_eq!(0, list.len());
-    }
+        backend.put("/later", b"1").await.unwrap();
+        assert!(matches!(backend.revert("/later", first).await, Err(Error::KeyNotFound { .. })));
 
+        backend.prune_log(3.into()).await.unwrap();
+        assert!(matches!(backend.revert("/config", first).await, Err(Error::Compacted { .. })));
+    }
 
     #[tokio::test]
     #[traced_test]
-    async fn solo_key_crud() {
+    async fn max_concurrent_writes() {
         let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
-
         let datasource = get_random_datasource(&temp_dir);
-        let pool_opts = SqlitePoolOptions::default();
-
-        let backend = SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap();
+        let config = SqliteConfig::new().max_concurrent_writes(NonZeroUsize::new(1).unwrap());
+        let backend = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await.unwrap();
 
-        let key = "/root/health";
-        let value = b"OK";
-        let null = backend.get(key, None).await.unwrap();
-        assert!(null.is_none());
+        // Without the limit, racing puts can fail with SQLITE_LOCKED on the shared cache.
+        let writers: Vec<_> = (0..200).map(|i| {
+            let backend = backend.clone();
+            tokio::spawn(async move { backend.put(&format!("/load/{}", i), b"1").await })
+        }).collect();
+        for writer in writers {
+            writer.await.unwrap().unwrap();
+        }
+        assert_eq!(200, backend.count("/load/").await.unwrap());
+        assert_eq!(200, backend.current_revision().await.unwrap());
 
-        let expected_revision = backend.put(key, value).await.unwrap();
-        assert_eq!(1, expected_revision);
+        let mut batch = backend.begin().await.unwrap();
+        batch.put("/load/batch", b"1").await.unwrap();
+        assert!(matches!(backend.put("/load/other", b"1").await, Err(Error::WritesHeld { held: 1 })));
+        batch.commit().await.unwrap();
+        backend.put("/load/other", b"1").await.unwrap();
 
+        backend.close().await;
+        assert!(matches!(backend.put("/load/late", b"1").await, Err(Error::Closed)));
+    }
 
-        let count = backend.count(key).await.unwrap();
-        assert_eq!(1, count);
+    #[tokio::test]
+    #[traced_test]
+    async fn revision_map() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
-        let kv = backend.get(key, None).await.unwrap().unwrap();
-        assert_eq!(kv.value().as_ref().unwrap(), value);
-        assert_eq!(1, *kv.create_revision());
-        assert_eq!(1, *kv.mod_revision());
+        backend.put("/sync/a", b"1").await.unwrap();
+        backend.put("/sync/b", b"1").await.unwrap();
+        backend.put("/sync/gone", b"1").await.unwrap();
+        backend.put("/other", b"1").await.unwrap();
+        let before = backend.revision_map("/sync/").await.unwrap();
+        assert_eq!(vec!["/sync/a", "/sync/b", "/sync/gone"], before.keys().collect::<Vec<_>>());
 
-        let new_value = b"NOT OKAY";
+        backend.put("/sync/a", b"2").await.unwrap();
+        backend.delete("/sync/gone").await.unwrap();
+        let after = backend.revision_map("/sync/").await.unwrap();
+        assert_eq!(vec!["/sync/a", "/sync/b"], after.keys().collect::<Vec<_>>());
+        assert_eq!(1, before["/sync/a"]);
+        assert_eq!(5, after["/sync/a"]);
+        assert_eq!(before["/sync/b"], after["/sync/b"]);
+    }
 
-        let expected_revision = backend.put(key, new_value).await.unwrap();
-        assert_eq!(2, expected_revision);
+    #[tokio::test]
+    #[traced_test]
+    async fn buffered_backend() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+        let buffered = BufferedBackend::new(backend.clone(), 1000, Duration::from_secs(3600));
 
-        let count = backend.count(key).await.unwrap();
-        assert_eq!(1, count);
+        for i in 0..100 {
+            buffered.put(&format!("/buffered/{}", i), b"1").await.unwrap();
+        }
+        buffered.put("/buffered/0", b"2").await.unwrap();
+        assert_eq!(101, buffered.pending());
+        assert_eq!(0, backend.count("/buffered/").await.unwrap());
 
-        let kv = backend.get(key, None).await.unwrap().unwrap();
-        assert_eq!(kv.value().as_ref().unwrap(), new_value);
-        assert_eq!(1, *kv.create_revision());
-        assert_eq!(2, *kv.mod_revision());
+        let revisions = buffered.flush().await.unwrap();
+        assert_eq!(101, revisions.len());
+        assert_eq!(0, buffered.pending());
+        assert_eq!(100, backend.count("/buffered/").await.unwrap());
+        assert_eq!(Some(b"2".to_vec()), *backend.get("/buffered/0", None).await.unwrap().unwrap().value());
+        assert!(buffered.flush().await.unwrap().is_empty());
 
-        let revision = backend.delete(key).await.unwrap();
-        assert_eq!(3, revision);
+        // A full buffer flushes on the put that fills it, a dropped one from a spawned task.
+        let buffered = BufferedBackend::new(backend.clone(), 10, Duration::from_secs(3600));
+        for i in 0..15 {
+            buffered.put(&format!("/full/{}", i), b"1").await.unwrap();
+        }
+        assert_eq!(10, backend.count("/full/").await.unwrap());
+        drop(buffered);
+        let kv = backend.wait_for("/full/14", |_| true, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(Some(b"1".to_vec()), *kv.value());
+        assert_eq!(15, backend.count("/full/").await.unwrap());
+    }
 
-        let kv = backend.get(key, None).await.unwrap();
-        assert!(kv.is_none());
+    #[tokio::test]
+    #[traced_test]
+    async fn buffered_backend_flushes_periodically() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+        let buffered = BufferedBackend::new(backend.clone(), 1000, Duration::from_millis(50));
 
-        let revision = backend.delete(key).await.unwrap();
-        assert_eq!(3, revision);
-        let count = backend.count(key).await.unwrap();
-        assert_eq!(0, count);
+        buffered.put("/ticked", b"1").await.unwrap();
+        backend.wait_for("/ticked", |_| true, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(0, buffered.pending());
     }
 
-
     #[tokio::test]
     #[traced_test]
-    async fn list_two() {
+    async fn value_size_histogram() {
         let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
-        let datasource = get_random_datasource(&temp_dir);
-        let pool_opts = SqlitePoolOptions::default();
+        for (name, size) in [("empty", 0), ("tiny", 5), ("small", 10), ("medium", 99), ("large", 100), ("huge", 5000)] {
+            backend.put(&format!("/sizes/{}", name), &vec![b'x'; size]).await.unwrap();
+        }
+        backend.put("/sizes/shrunk", &[b'x'; 5000]).await.unwrap();
+        backend.put("/sizes/shrunk", b"x").await.unwrap();
+        backend.put("/sizes/gone", b"x").await.unwrap();
+        backend.delete("/sizes/gone").await.unwrap();
+        backend.put("/elsewhere", b"x").await.unwrap();
 
-        let backend = SqliteBackend::new(Path::new(datasource.as_str()), pool_opts).await.unwrap();
+        assert_eq!(vec![3, 2, 1, 1], backend.value_size_histogram("/sizes/", &[10, 100, 1000]).await.unwrap());
+        assert_eq!(vec![0, 7], backend.value_size_histogram("/sizes/", &[0]).await.unwrap());
+        assert_eq!(vec![7], backend.value_size_histogram("/sizes/", &[]).await.unwrap());
+        assert_eq!(vec![0, 0], backend.value_size_histogram("/nothing/", &[10]).await.unwrap());
+    }
 
-        let key_1 = "/root/health";
-        let key_2 = "/root/status";
-        let value = b"OK";
+    #[tokio::test]
+    #[traced_test]
+    async fn read_txn() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
+        backend.put("/app/a", b"1").await.unwrap();
 
-        let expected_revision = backend.put(key_1, value).await.unwrap();
-        assert_eq!(1, expected_revision);
+        let mut txn = backend.read_txn().await.unwrap();
+        assert_eq!(1, txn.revision());
+        assert_eq!(1, txn.count("/app/").await.unwrap());
 
+        let writer = backend.clone();
+        let write = tokio::spawn(async move {
+            writer.put("/app/a", b"2").await.unwrap();
+            writer.put("/app/b", b"1").await.unwrap()
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
 
-        let expected_revision = backend.put(key_2, value).await.unwrap();
-        assert_eq!(2, expected_revision);
+        assert_eq!(Some(b"1".to_vec()), *txn.get("/app/a").await.unwrap().unwrap().value());
+        assert_eq!(1, txn.list_current("/app/", 0, false).await.unwrap().len());
+        assert_eq!(1, txn.count("/app/").await.unwrap());
+        txn.finish().await.unwrap();
 
-        let count = backend.count("/root/").await.unwrap();
-        assert_eq!(2, count);
+        write.await.unwrap();
+        assert_eq!(2, backend.count("/app/").await.unwrap());
+    }
 
-        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
-        assert_eq!(2, kvs.len());
+    #[tokio::test]
+    #[traced_test]
+    async fn find_duplicates() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
+        backend.put("/a", b"1").await.unwrap();
+        backend.put("/a", b"2").await.unwrap();
+        backend.put("/b", b"1").await.unwrap();
+        backend.delete("/b").await.unwrap();
+        backend.put("/b", b"2").await.unwrap();
+        assert!(backend.find_duplicates().await.unwrap().is_empty());
 
-        let kvs = backend.list_current("/root/", 1, false).await.unwrap();
-        assert_eq!(1, kvs.len());
+        sqlx::query(r###"
+            INSERT INTO sumkin(id, name, created, deleted, create_revision, prev_revision, value) VALUES
+                (10, '/a', 1, 0, 10, NULL, x'33')
+        "###).execute(&backend.pool).await.unwrap();
+        assert_eq!(vec![("/a".to_string(), vec![1.into(), 10.into()])], backend.find_duplicates().await.unwrap());
 
+        backend.delete("/a").await.unwrap();
+        backend.put("/a", b"4").await.unwrap();
+        assert!(backend.find_duplicates().await.unwrap().is_empty());
+    }
 
-        let expected_revision = backend.put(key_1, value).await.unwrap();
-        assert_eq!(3, expected_revision);
+    #[tokio::test]
+    #[traced_test]
+    async fn put_keeps_lease() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let backend = get_backend(&temp_dir).await;
 
+        backend.put("/svc/node", b"10.0.0.1").await.unwrap();
+        let lease = backend.grant_lease(30).await.unwrap();
+        backend.attach_lease("/svc/node", Some(lease)).await.unwrap();
 
-        let expected_revision = backend.put(key_2, value).await.unwrap();
-        assert_eq!(4, expected_revision);
+        backend.put("/svc/node", b"10.0.0.2").await.unwrap();
+        backend.append("/svc/node", b":80").await.unwrap();
+        let kv = backend.get("/svc/node", None).await.unwrap().unwrap();
+        assert_eq!(Some(b"10.0.0.2:80".to_vec()), *kv.value());
+        assert_eq!(Some(lease), *kv.lease());
 
-        let count = backend.count("/root/").await.unwrap();
-        assert_eq!(2, count);
+        backend.attach_lease("/svc/node", None).await.unwrap();
+        backend.put("/svc/node", b"10.0.0.3").await.unwrap();
+        assert_eq!(None, *backend.get("/svc/node", None).await.unwrap().unwrap().lease());
 
-        let revision = backend.delete("/root").await.unwrap();
-        assert_eq!(4, revision);
+        // A key created again after a delete starts without the old lease.
+        backend.attach_lease("/svc/node", Some(lease)).await.unwrap();
+        backend.delete("/svc/node").await.unwrap();
+        backend.put("/svc/node", b"10.0.0.4").await.unwrap();
+        assert_eq!(None, *backend.get("/svc/node", None).await.unwrap().unwrap().lease());
+    }
 
-        let revision = backend.delete(key_1).await.unwrap();
-        assert_eq!(5, revision);
-        let revision = backend.delete(key_1).await.unwrap();
-        assert_eq!(5, revision);
-        let count = backend.count("/root/").await.unwrap();
-        assert_eq!(1, count);
-        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
-        assert_eq!(1, kvs.len());
+    #[tokio::test]
+    #[traced_test]
+    async fn stored_version() {
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let datasource = get_random_datasource(&temp_dir);
+        let backend = get_backend(&temp_dir).await;
+        assert_eq!(Some(SqliteBackend::schema_version()), backend.stored_version().await.unwrap());
 
+        let newer = SqliteBackend::schema_version() + 1;
+        backend.pool.execute(format!("PRAGMA user_version = {}", newer).as_str()).await.unwrap();
+        backend.close().await;
 
+        let reopened = SqliteBackend::new(Path::new(datasource.as_str()), SqlitePoolOptions::default()).await.unwrap();
+        assert!(logs_contain("is newer than the supported"));
+        assert_eq!(Some(newer), reopened.stored_version().await.unwrap());
+        // Lets the test see whether a refused open still applied the schema.
+        reopened.pool.execute("DROP INDEX sumkin_name_index").await.unwrap();
+        reopened.close().await;
 
-        let revision = backend.delete(key_2).await.unwrap();
-        assert_eq!(6, revision);
-        let revision = backend.delete(key_2).await.unwrap();
-        assert_eq!(6, revision);
-        let count = backend.count("/root/").await.unwrap();
-        assert_eq!(0, count);
-        let kvs = backend.list_current("/root/", -1, false).await.unwrap();
-        assert_eq!(0, kvs.len());
+        let config = SqliteConfig::new().refuse_newer_schema(true);
+        let refused = SqliteBackend::with_config(Path::new(datasource.as_str()), SqlitePoolOptions::default(), config).await;
+        assert!(matches!(refused, Err(Error::SchemaMismatch { .. })));
+
+        let pool = SqlitePoolOptions::default().connect_with(SqliteConnectOptions::new().filename(datasource.as_str())).await.unwrap();
+        let index: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = 'sumkin_name_index'").fetch_one(&pool).await.unwrap();
+        assert_eq!(0, index);
     }
 }
@@ -3,6 +3,7 @@ use async_trait::async_trait;
 use derive_getters::Getters;
 use crate::Revision;
 use sqlx::FromRow;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Getters, FromRow, Clone)]
 pub struct KeyValue {
@@ -11,19 +12,177 @@ pub struct KeyValue {
     create_revision: Revision,
     #[sqlx(rename = "theid")]
     mod_revision: Revision,
+    /// The revision of this key that this one replaced, if any.
+    #[sqlx(default)]
+    prev_revision: Option<Revision>,
     #[sqlx(default)]
     value: Option<Vec<u8>>,
     #[sqlx(default)]
+    lease: Option<i64>,
+    #[sqlx(default)]
+    tag: Option<String>,
+    /// Whether this revision is a delete (tombstone) rather than a write.
+    #[sqlx(default)]
+    deleted: bool,
+    #[sqlx(default)]
+    content_type: Option<String>,
+    /// Wall-clock write time in milliseconds since the Unix epoch.
+    #[sqlx(default)]
+    mtime: Option<i64>,
+
+}
+
+/// Per-key details gathered in a single query.
+#[derive(Debug, Getters, FromRow, Clone)]
+pub struct KeyStat {
+    create_revision: Revision,
+    mod_revision: Revision,
+    /// Number of revisions since the key was (re)created.
+    version: i64,
+    value_size: i64,
+    #[sqlx(default)]
     lease: Option<i64>
+}
 
+/// Write volume derived from the rows still in the log, so pruning lowers it.
+#[derive(Debug, Getters, FromRow, Clone, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Every revision written, including deletes.
+    rows_written: i64,
+    /// Revisions that created a key.
+    creates: i64,
+    tombstones: i64,
+    /// Total length of the values written.
+    bytes_written: i64
 }
 
+/// A backend behind dynamic dispatch, for applications that pick one at runtime.
+pub type BoxedBackend = Box<dyn Backend + Send + Sync>;
+
 #[async_trait]
 pub trait Backend {
     async fn size(&self) -> SumkinResult<u64>;
     async fn current_revision(&self) -> SumkinResult<Revision>;
+    /// Whether anything was written after `token`, a revision from an earlier
+    /// `current_revision` or write. A cheap check before redoing expensive work.
+    async fn changed_since(&self, token: Revision) -> SumkinResult<bool> {
+        Ok(self.current_revision().await? > token)
+    }
+    /// The revision the log was last compacted to, or 0 if it never was.
+    async fn compact_revision(&self) -> SumkinResult<Revision>;
     async fn count(&self, prefix: &str) -> SumkinResult<u64>;
+    /// Number of raw log rows: every revision still stored, including deletes and the
+    /// compaction marker. Compare with `count` to gauge how much compaction would reclaim.
+    async fn log_count(&self) -> SumkinResult<u64>;
+    /// Totals over the raw log, for gauging write amplification alongside `log_count`.
+    async fn write_stats(&self) -> SumkinResult<WriteStats>;
+    /// Whether no live key exists at all. Cheaper than `count`.
+    async fn is_empty(&self) -> SumkinResult<bool>;
+    /// Whether any live key exists under `prefix`. Cheaper than `count`, as it stops at the
+    /// first match.
+    async fn prefix_exists(&self, prefix: &str) -> SumkinResult<bool>;
+    /// Counts current keys grouped by their first path segment (`/a/b` and `a/b` both count
+    /// towards `a`), ordered by segment.
+    async fn count_by_top_level(&self) -> SumkinResult<Vec<(String, u64)>>;
+    /// Counts keys under `prefix` as `(live, tombstoned)`, where a key is tombstoned if
+    /// its latest revision is a delete.
+    async fn count_all(&self, prefix: &str) -> SumkinResult<(u64, u64)>;
+    /// Returns the first of `names` that currently exists, read in a single transaction.
+    async fn get_first(&self, names: &[&str]) -> SumkinResult<Option<(String, KeyValue)>>;
+    /// Returns the current value of each of `names` that exists, keyed by name; missing keys are
+    /// left out. Read in a single transaction.
+    async fn get_map(&self, names: &[&str]) -> SumkinResult<HashMap<String, KeyValue>>;
+    /// Returns stats for the exact key `name`, or `None` if it doesn't currently exist.
+    async fn stat(&self, name: &str) -> SumkinResult<Option<KeyStat>>;
+    /// Writes `value` as the new revision of `name`, creating it if needed. Updating a key
+    /// keeps its lease; use `attach_lease` to change or drop it.
     async fn put(&self, name: &str, value: &[u8]) -> SumkinResult<Revision>;
+    /// Creates a lease lasting `ttl_seconds` and returns its id.
+    async fn grant_lease(&self, ttl_seconds: i64) -> SumkinResult<i64>;
+    /// Extends lease `lease_id` by its ttl from now. Fails with `Error::LeaseNotFound` if it
+    /// doesn't exist.
+    async fn keep_alive(&self, lease_id: i64) -> SumkinResult<()>;
+    /// The distinct lease ids current keys are attached to, in ascending order.
+    async fn active_leases(&self) -> SumkinResult<Vec<i64>>;
+    /// Writes a new revision of `name` attached to `lease_id`, or detached from any lease if
+    /// `None`, keeping its value and create_revision.
+    async fn attach_lease(&self, name: &str, lease_id: Option<i64>) -> SumkinResult<Revision>;
+    /// Writes a new revision of `name` identical to the current one (value, lease, tag and
+    /// content type) except for its mtime, to signal watchers without changing anything.
+    /// Fails with `Error::KeyNotFound` if it doesn't exist.
+    async fn touch(&self, name: &str) -> SumkinResult<Revision>;
+    /// Creates `name` with `value` if it doesn't exist, returning `true` and the new key, or
+    /// returns `false` and the key as it is without writing. Checking and creating happen in
+    /// one statement, so of several racing callers exactly one sees `true`.
+    async fn create_or_get(&self, name: &str, value: &[u8]) -> SumkinResult<(bool, KeyValue)>;
+    /// Puts every entry in one transaction, returning one revision per entry.
+    ///
+    /// With `coalesce`, consecutive entries for the same key are collapsed into a single write
+    /// of the last value; the intermediate values never reach the log (and so are not visible to
+    /// watchers or history), and each collapsed entry reports the revision of that one write.
+    async fn put_batch(&self, entries: &[(&str, &[u8])], coalesce: bool) -> SumkinResult<Vec<Revision>>;
+    /// Puts every entry in one transaction if no live key exists under `prefix`, returning
+    /// whether it did. The check and the writes share the transaction, so of several racing
+    /// callers at most one seeds. Entries aren't required to be under `prefix`.
+    async fn bootstrap_if_empty(&self, prefix: &str, entries: &[(&str, &[u8])]) -> SumkinResult<bool>;
+    /// Writes new values for existing keys in one transaction, keeping their create_revision.
+    /// Fails with `Error::KeyNotFound`, writing nothing, if any key doesn't exist.
+    async fn put_batch_update(&self, entries: &[(&str, &[u8])]) -> SumkinResult<Vec<Revision>>;
+    /// Writes the value of `a` to `b` and the value of `b` to `a` in one transaction, returning
+    /// the new revisions of `a` and `b`. Fails with `Error::KeyNotFound`, writing nothing, if
+    /// either key doesn't exist.
+    async fn swap(&self, a: &str, b: &str) -> SumkinResult<(Revision, Revision)>;
+    /// Same as `put`, but safe to retry: the first call with a given `request_id` writes and
+    /// records it, and later calls with the same id write nothing and return the original
+    /// revision, even if `name` or `value` differ. Request ids are kept indefinitely.
+    async fn put_idempotent(&self, name: &str, value: &[u8], request_id: &str) -> SumkinResult<Revision>;
+    /// Writes the current value of `name` followed by `data` as a new revision, reading and
+    /// writing in one transaction, or creates `name` with just `data` if it doesn't exist.
+    async fn append(&self, name: &str, data: &[u8]) -> SumkinResult<Revision>;
+    /// Writes the value `name` had at `to_revision` as a new revision, leaving the history in
+    /// between intact. Fails with `Error::Compacted` if `to_revision` is older than the
+    /// compaction point, and with `Error::KeyNotFound` if the key didn't exist at it.
+    async fn revert(&self, name: &str, to_revision: Revision) -> SumkinResult<Revision>;
+    /// Same as `put`, but stores `tag` alongside the new revision. Tags are per revision:
+    /// a later plain `put` of the same key clears it.
+    async fn put_tagged(&self, name: &str, tag: &str, value: &[u8]) -> SumkinResult<Revision>;
+    /// Same as `put`, but records the value's `content_type` on the new revision. Like tags,
+    /// content types are per revision.
+    async fn put_with_meta(&self, name: &str, value: &[u8], content_type: &str) -> SumkinResult<Revision>;
+    /// Lists current keys whose latest revision has the given `content_type`.
+    async fn list_by_content_type(&self, content_type: &str) -> SumkinResult<Vec<KeyValue>>;
+    /// Sets annotation `label` to `value` on the current revision of `name`, without writing a
+    /// new revision. Like tags, annotations belong to a revision: the next write of `name`
+    /// starts with none. Fails with `Error::KeyNotFound` if `name` doesn't exist.
+    async fn set_annotation(&self, name: &str, label: &str, value: &str) -> SumkinResult<()>;
+    /// The annotations on the current revision of `name`, by label; empty if it doesn't exist.
+    async fn get_annotations(&self, name: &str) -> SumkinResult<HashMap<String, String>>;
+    /// Lists current keys annotated with `label` set to `value`.
+    async fn list_annotated(&self, label: &str, value: &str) -> SumkinResult<Vec<KeyValue>>;
+    /// Maps every current key under `prefix` to its mod_revision, without reading values, so
+    /// a client can compare it with its own copy and fetch only the keys that changed.
+    async fn revision_map(&self, prefix: &str) -> SumkinResult<BTreeMap<String, Revision>>;
+    /// Lists every current key under `prefix` in name order with its mod_revision and the
+    /// CRC-32 (ISO-HDLC, as in zlib) of its value, so two stores can be compared without
+    /// transferring values. A missing value hashes like an empty one.
+    async fn list_hashes(&self, prefix: &str) -> SumkinResult<Vec<(String, Revision, u32)>>;
+    /// Counts current keys under `prefix` by value length. `buckets` are ascending exclusive
+    /// upper bounds in bytes: entry `i` of the result counts values shorter than `buckets[i]`
+    /// and not shorter than `buckets[i - 1]`, and one extra entry at the end counts the rest.
+    /// Missing values count as empty.
+    async fn value_size_histogram(&self, prefix: &str, buckets: &[i64]) -> SumkinResult<Vec<u64>>;
+    /// Lists current keys under `prefix` whose value is empty or missing.
+    async fn list_empty_values(&self, prefix: &str) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current (non-deleted) keys whose latest revision carries `tag`.
+    async fn list_by_tag(&self, tag: &str) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current keys under `prefix` last modified within `[start_ms, end_ms)`.
+    async fn modified_between(&self, prefix: &str, start_ms: i64, end_ms: i64) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current keys under `prefix` created after `revision`, leaving out keys that existed
+    /// then and were only updated since. A key deleted and created again counts as new.
+    async fn created_since(&self, prefix: &str, revision: Revision) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current keys under `prefix` last modified more than `older_than_ms` ago. Keys
+    /// without a recorded mtime (e.g. imported from kine) are never listed.
+    async fn list_stale(&self, prefix: &str, older_than_ms: i64) -> SumkinResult<Vec<KeyValue>>;
     async fn get(&self, name: &str, revision: Option<Revision>) -> SumkinResult<Option<KeyValue>> {
         if let Some(_r) = revision {
             unimplemented!();
@@ -32,9 +191,86 @@ pub trait Backend {
             Ok(kv.into_iter().next())
         }
     }
+    /// Returns `length` bytes of the current value of `name` starting at the 0-based `offset`,
+    /// without reading the rest of the value. The range is cut short at the end of the value;
+    /// negative offsets and lengths count as 0. `None` if the key doesn't exist.
+    async fn get_range_bytes(&self, name: &str, offset: i64, length: i64) -> SumkinResult<Option<Vec<u8>>>;
+    /// Lists current keys below `prefix` if it ends with `/`, otherwise the exact key `prefix`
+    /// (the SQLite backend can be configured to read it as a subtree instead, see
+    /// `SqliteConfig::prefix_mode`). The prefix is literal: `%` and `_` match only themselves.
     async fn list_current(&self, prefix: &str, limit: i64, include_deleted: bool) -> SumkinResult<Vec<KeyValue>>;
+    /// Runs `list_current` (without deleted keys) for each of `prefixes` in a single
+    /// transaction, so all listings reflect the same revision. Keyed by prefix.
+    async fn list_prefixes(&self, prefixes: &[&str], limit_per: i64) -> SumkinResult<HashMap<String, Vec<KeyValue>>>;
+    /// Same as `list_current` (without deleted keys), collected into a map by key name. Each
+    /// name appears at most once since only the latest revision of a key is listed.
+    async fn list_map(&self, prefix: &str, limit: i64) -> SumkinResult<HashMap<String, KeyValue>> {
+        let kvs = self.list_current(prefix, limit, false).await?;
+        Ok(kvs.into_iter().map(|kv| (kv.key().clone(), kv)).collect())
+    }
+    /// Same as `list_current` (without deleted keys), but ordered by the last path segment of
+    /// each key read as an integer, so `/item/9` comes before `/item/10`. Segments that aren't
+    /// numbers sort as 0; ties are ordered by name.
+    async fn list_numeric_sorted(&self, prefix: &str, limit: i64) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists the current keys under `prefix` that fall in shard `shard` of `total_shards`,
+    /// in the same order as `list_current`. Shards are assigned by a stable hash of the key
+    /// name, so every key lands in exactly one shard and stays there across processes.
+    /// Fails with `Error::InvalidArgument` if `total_shards` is 0.
+    async fn list_shard(&self, prefix: &str, shard: u32, total_shards: u32) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current keys whose name matches the SQL `LIKE` `pattern`, with `\` as the escape
+    /// character.
+    async fn list_matching(&self, pattern: &str, limit: i64) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current keys in `[key, range_end)` ordered by key, following etcd conventions: an
+    /// empty `range_end` selects only `key`, and `"\0"` means no upper bound. A prefix maps onto
+    /// a range via `keys::prefix_range_end`. Keys must be valid UTF-8.
+    async fn range_keys(&self, key: &[u8], range_end: &[u8], limit: i64) -> SumkinResult<Vec<KeyValue>>;
+    /// The current key with the greatest name `<= key`, by byte order like `range_keys`.
+    async fn floor(&self, key: &str) -> SumkinResult<Option<KeyValue>>;
+    /// The current key with the least name `>= key`.
+    async fn ceiling(&self, key: &str) -> SumkinResult<Option<KeyValue>>;
+    /// Counts current keys in `[key, range_end)`, with the same conventions as `range_keys`.
+    async fn count_range(&self, key: &[u8], range_end: &[u8]) -> SumkinResult<u64>;
 
+    /// Tombstones `name`, returning the revision of the delete. Deleting a key that doesn't
+    /// exist writes nothing and returns the current revision.
     async fn delete(&self, name: &str) -> SumkinResult<Revision>;
+    /// Same as `delete`, but also reports whether a live key was tombstoned. When it wasn't,
+    /// nothing is written and the revision is the current one.
+    async fn delete_checked(&self, name: &str) -> SumkinResult<(bool, Revision)>;
+    /// Deletes every current key matching `prefix` in one transaction, returning each key's
+    /// value as it was just before the delete.
+    async fn delete_range_prev(&self, prefix: &str) -> SumkinResult<Vec<KeyValue>>;
+    /// Folds the log after `revision` into the latest state of each key under `prefix` written
+    /// since, keyed by name. A key deleted since maps to its tombstone (`deleted()` is true).
+    /// Fails with `Error::Compacted` if `revision` is below the compaction point, since deletes
+    /// in between may be gone.
+    async fn changes_since(&self, prefix: &str, revision: Revision) -> SumkinResult<HashMap<String, KeyValue>>;
+    /// Returns the last `limit` log entries across all keys, newest first, including deletes.
+    async fn recent(&self, limit: i64) -> SumkinResult<Vec<KeyValue>>;
+    /// Lists current keys under `prefix`, most recently modified first, up to `limit`.
+    async fn recent_under(&self, prefix: &str, limit: i64) -> SumkinResult<Vec<KeyValue>>;
+    /// Returns the raw log entry written at `revision`, including deletes.
+    async fn event_at(&self, revision: Revision) -> SumkinResult<Option<KeyValue>>;
+    /// Removes every key and its whole history, restarting revisions from 1 (unless the backend
+    /// keeps its own revision counter), and bumps the generation. Watchers and callers holding revisions from before have to resync.
+    async fn clear(&self) -> SumkinResult<()>;
+    /// A counter that changes whenever the log is reset or replaced (`clear`, imports), so a
+    /// revision is only meaningful together with the generation it was read in.
+    async fn generation(&self) -> SumkinResult<u64>;
+    /// Physically removes log rows with revision `<= up_to_revision` that are not the live
+    /// current row of their key, and records `up_to_revision` as the compaction point.
+    /// History below that revision is lost. Returns the number of rows removed.
+    async fn prune_log(&self, up_to_revision: Revision) -> SumkinResult<u64>;
+    /// Same as `prune_log`, but only for keys under `prefix`, so hot namespaces can be compacted
+    /// incrementally. The compaction point is not recorded, since history outside `prefix` is
+    /// still there: reads and watches below `up_to_revision` under `prefix` find nothing rather
+    /// than failing with `Error::Compacted`.
+    async fn compact_prefix(&self, prefix: &str, up_to_revision: Revision) -> SumkinResult<u64>;
+
+    /// Boxes this backend as a `BoxedBackend`.
+    fn into_boxed(self) -> BoxedBackend where Self: Sized + Send + Sync + 'static {
+        Box::new(self)
+    }
     //async fn get_revision(&self, revision: i64) -> SumkinResult<()>;
     //async fn get(key: &str, revision: i64) -> SumkinResult<KeyValue>;
     //async fn create(key: &str, value: Vec<u8>, lease: i64) -> SumkinResult<i64>;
@@ -0,0 +1,67 @@
+use crate::traits::KeyValue;
+use crate::Revision;
+use derive_getters::Getters;
+
+/// A single change observed in the log.
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    /// A key was created or updated; carries the new revision, and with `with_prev_kv` the
+    /// revision it replaced, if any.
+    Put { kv: KeyValue, prev_kv: Option<KeyValue> },
+    /// A key was deleted; carries the tombstone, whose `value` is empty, and with
+    /// `with_prev_kv` the revision that was deleted.
+    Delete { kv: KeyValue, prev_kv: Option<KeyValue> },
+    /// The log was compacted past the watch position, so events may be missing. This is always
+    /// the last event of a stream; the consumer has to resync from current state.
+    Compacted { compact_revision: Revision },
+}
+
+impl WatchEvent {
+    /// The key-value carried by a `Put` or `Delete`.
+    pub fn kv(&self) -> Option<&KeyValue> {
+        match self {
+            WatchEvent::Put { kv, .. } | WatchEvent::Delete { kv, .. } => Some(kv),
+            WatchEvent::Compacted { .. } => None,
+        }
+    }
+
+    /// The previous revision of the key carried by a `Put` or `Delete`, when the watch was
+    /// asked for it and that revision is still in the log.
+    pub fn prev_kv(&self) -> Option<&KeyValue> {
+        match self {
+            WatchEvent::Put { prev_kv, .. } | WatchEvent::Delete { prev_kv, .. } => prev_kv.as_ref(),
+            WatchEvent::Compacted { .. } => None,
+        }
+    }
+}
+
+impl From<KeyValue> for WatchEvent {
+    fn from(kv: KeyValue) -> Self {
+        if *kv.deleted() {
+            WatchEvent::Delete { kv, prev_kv: None }
+        } else {
+            WatchEvent::Put { kv, prev_kv: None }
+        }
+    }
+}
+
+/// The kind of mutation reported to a change callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Put,
+    Delete,
+}
+
+/// A committed mutation, passed to the callback registered with `SqliteBackend::on_change`.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+pub struct ChangeEvent {
+    op: ChangeOp,
+    name: String,
+    revision: Revision,
+}
+
+impl ChangeEvent {
+    pub(crate) fn new(op: ChangeOp, name: &str, revision: Revision) -> Self {
+        ChangeEvent { op, name: name.to_owned(), revision }
+    }
+}
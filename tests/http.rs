@@ -0,0 +1,65 @@
+#![cfg(feature = "http")]
+
+use std::path::Path;
+use std::sync::Arc;
+
+use sqlx::sqlite::SqlitePoolOptions;
+use sumkin::http::router;
+use sumkin::sqlite::SqliteBackend;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Sends one HTTP/1.1 request and returns the status code and body.
+async fn request(addr: std::net::SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method, path, body.len()
+    );
+    stream.write_all(head.as_bytes()).await.unwrap();
+    stream.write_all(body).await.unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+#[tokio::test]
+async fn put_get_over_http() {
+    let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+    let datasource = temp_dir.path().join("state.db");
+    let backend = SqliteBackend::new(Path::new(&datasource), SqlitePoolOptions::default()).await.unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router(Arc::new(backend))).await.unwrap();
+    });
+
+    let (status, _) = request(addr, "GET", "/kv/root/health", b"").await;
+    assert_eq!(404, status);
+
+    let (status, body) = request(addr, "PUT", "/kv/root/health", b"OK").await;
+    assert_eq!(200, status);
+    assert_eq!(b"1", body.as_slice());
+
+    let (status, body) = request(addr, "GET", "/kv/root/health", b"").await;
+    assert_eq!(200, status);
+    assert_eq!(b"OK", body.as_slice());
+
+    request(addr, "PUT", "/kv/root/status", b"green").await;
+    let (status, body) = request(addr, "GET", "/list/root/", b"").await;
+    assert_eq!(200, status);
+    assert_eq!(b"/root/health\n/root/status", body.as_slice());
+
+    let (status, body) = request(addr, "DELETE", "/kv/root/health", b"").await;
+    assert_eq!(200, status);
+    assert_eq!(b"3", body.as_slice());
+    let (status, _) = request(addr, "GET", "/kv/root/health", b"").await;
+    assert_eq!(404, status);
+}